@@ -33,6 +33,8 @@ bindgen "$BASEDIR/TracyC.h" \
   -- \
   -DTRACY_ENABLE
 sed -i 's/pub type/type/g' 'tracy-client-sys/src/generated.rs'
+sed -i "s/^const TRACY_VERSION: &str = \".*\";$/const TRACY_VERSION: \&str = \"${TAG#v}\";/" \
+    'tracy-client-sys/build.rs'
 
 for REQUIRED_FILE in ${REQUIRED[@]}
 do
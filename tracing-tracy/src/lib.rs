@@ -14,9 +14,109 @@
 //!
 //! * Only span entries and exits are recorded;
 //! * Events show up as messages in Tracy, however Tracy can struggle with large numbers of
-//! messages;
-//! * Some additional functionality such as plotting and memory allocation profiling is only
-//! available as part of the [tracy-client](tracy_client) crate.
+//!   messages;
+//! * Fields whose names start with `tracy.` are reserved for instructions to this layer, such as
+//!   the ones described below, and are not shown in Tracy. Events with only such fields do not
+//!   show up as messages;
+//! * Some additional functionality is only available as part of the [tracy-client](tracy_client)
+//!   crate.
+//!
+//! # Memory profiling
+//!
+//! Allocations are not visible to `tracing`, but they can be profiled by registering a
+//! [`ProfiledAllocator`](ProfiledAllocator) as the global allocator. The
+//! [`global_allocator!`](global_allocator) macro does this without having to depend on
+//! `tracy-client` directly:
+//!
+//! ```
+//! use tracing_subscriber::layer::SubscriberExt;
+//!
+//! tracing_tracy::global_allocator!(std::alloc::System, 16);
+//!
+//! tracing::subscriber::set_global_default(
+//!     tracing_subscriber::registry().with(tracing_tracy::TracyLayer::new()),
+//! ).expect("set up the subscriber");
+//! ```
+//!
+//! # Frames
+//!
+//! Events with a `tracy.frame_mark = true` field mark the end of a frame. Alternatively, a span
+//! with a `tracy.frame_boundary` field marks the end of a frame in the secondary frame set named
+//! by the field when the span is closed, which suits applications with a span for every frame:
+//!
+//! ```
+//! # use tracing::info_span;
+//! for _ in 0..3 {
+//!     let _frame = info_span!("frame", tracy.frame_boundary = "render").entered();
+//! }
+//! ```
+//!
+//! The field has to be given when the span is created. Every distinct frame set name is leaked.
+//!
+//! # Callstacks
+//!
+//! A span with a numeric `tracy.stack_depth` field collects at most that many stack frames,
+//! instead of the number set with [`TracyLayer::with_stackdepth`](TracyLayer::with_stackdepth).
+//! This allows collecting deep callstacks only for the spans of interest:
+//!
+//! ```
+//! # use tracing::info_span;
+//! let _span = info_span!("deep", tracy.stack_depth = 32).entered();
+//! ```
+//!
+//! The field has to be given when the span is created.
+//!
+//! # Plots
+//!
+//! Events with a `tracy.plot` field add a point to the plot of that name. The point's value is
+//! taken from the numeric `value` field of the same event:
+//!
+//! ```
+//! # use tracing::{event, Level};
+//! event!(Level::INFO, tracy.plot = "fps", value = 60.0);
+//! ```
+//!
+//! The event is still shown as a message as well. Every distinct plot name is leaked, so the
+//! names should come from a small, fixed set.
+//!
+//! # Events as zones
+//!
+//! An event with a `tracy.as_zone = true` field is shown as an instantaneous zone on the timeline
+//! of its thread instead of as a message. The zone is named after the event, and the rendered
+//! fields are attached to it as text:
+//!
+//! ```
+//! # use tracing::info;
+//! info!(tracy.as_zone = true, bytes = 4096, "flushed the buffer");
+//! ```
+//!
+//! # OpenTelemetry
+//!
+//! The value of a span's `otel.span_id` field is attached to its zones as text, so that a zone
+//! can be correlated with the corresponding span of a distributed trace. Numeric ids are shown
+//! as 16 hexadecimal digits. The field may also be recorded after the span is created:
+//!
+//! ```
+//! # use tracing::{field, info_span};
+//! let span = info_span!("request", otel.span_id = field::Empty);
+//! span.record("otel.span_id", 0x00f0_67aa_0ba9_02b7_u64);
+//! ```
+//!
+//! # Logging to the console as well
+//!
+//! `TracyLayer` composes with other layers, such as the `fmt` layer of `tracing-subscriber`. With
+//! the `fmt` feature of this crate enabled, [`HideReservedFields`](HideReservedFields) keeps the
+//! `tracy.`-prefixed fields out of the console output:
+//!
+//! ```
+//! # #[cfg(feature = "fmt")] {
+//! use tracing_subscriber::layer::SubscriberExt;
+//! use tracing_tracy::{HideReservedFields, TracyLayer};
+//!
+//! let fmt = tracing_subscriber::fmt::layer().fmt_fields(HideReservedFields::default());
+//! let subscriber = tracing_subscriber::registry().with(TracyLayer::new()).with(fmt);
+//! # }
+//! ```
 //!
 //! # Important note
 //!
@@ -30,39 +130,138 @@
 //! As thus, you may want make sure to only enable the `tracing-tracy` crate conditionally, via the
 //! `enable` feature flag provided by this crate.
 //!
+//! With the `enable` feature disabled, [`TracyLayer`](TracyLayer) retains its entire API, but
+//! does not record anything. This is also how this crate should be used on targets that the Tracy
+//! client library does not support, such as `wasm32`.
+//!
 //! [Tracy]: https://github.com/wolfpld/tracy
 
-use std::{fmt::Write, collections::VecDeque, cell::RefCell};
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    collections::{hash_map::DefaultHasher, HashMap, VecDeque},
+    fmt::Write,
+    hash::{Hash, Hasher},
+    sync::atomic::{AtomicU32, Ordering},
+    time::{Duration, Instant},
+};
 use tracing_core::{
+    callsite::Identifier,
     field::{Field, Visit},
-    span::Id,
-    Event, Subscriber,
+    span::{Attributes, Id, Record},
+    Event, LevelFilter, Metadata, Subscriber,
 };
 use tracing_subscriber::{
     layer::{Context, Layer},
     registry,
 };
 
-use tracy_client::{Span, color_message, message, finish_continuous_frame};
+use tracy_client::{Plot, Span, color_message, message, finish_continuous_frame, intern_name};
+use tracy_client::{set_thread_name, MAX_MESSAGE_LEN, MAX_ZONE_NAME_LEN};
+
+pub use tracy_client::ProfiledAllocator;
+
+/// Register a [`ProfiledAllocator`](ProfiledAllocator) as the global allocator.
+///
+/// The first argument is the allocator to wrap, the second is the maximum number of stack frames
+/// to collect for each allocation and deallocation. The allocator can be given either as a path to
+/// a unit struct, such as `std::alloc::System`, or as `Type = expression`:
+///
+/// ```
+/// # struct MyAllocator(std::alloc::System);
+/// # unsafe impl std::alloc::GlobalAlloc for MyAllocator {
+/// #     unsafe fn alloc(&self, l: std::alloc::Layout) -> *mut u8 { self.0.alloc(l) }
+/// #     unsafe fn dealloc(&self, p: *mut u8, l: std::alloc::Layout) { self.0.dealloc(p, l) }
+/// # }
+/// tracing_tracy::global_allocator!(MyAllocator = MyAllocator(std::alloc::System), 0);
+/// ```
+///
+/// This may only be used once in the final binary, same as the `#[global_allocator]` attribute.
+#[macro_export]
+macro_rules! global_allocator {
+    ($inner:ty = $init:expr, $callstack_depth:expr) => {
+        #[global_allocator]
+        static TRACY_GLOBAL_ALLOCATOR: $crate::ProfiledAllocator<$inner> =
+            $crate::ProfiledAllocator::new($init, $callstack_depth);
+    };
+    ($inner:path, $callstack_depth:expr) => {
+        $crate::global_allocator!($inner = $inner, $callstack_depth);
+    };
+}
 
 thread_local! {
     /// A stack of spans currently active on the current thread.
-    static TRACY_SPAN_STACK: RefCell<VecDeque<(Span, u64)>> =
+    ///
+    /// Entries of spans that were not sampled have no zone. The last element counts the
+    /// re-entries coalesced into the entry.
+    static TRACY_SPAN_STACK: RefCell<VecDeque<(Option<Span>, u64, usize)>> =
         RefCell::new(VecDeque::with_capacity(16));
+
+    /// The last message emitted for an event and the number of times it was repeated since.
+    static LAST_MESSAGE: RefCell<(String, usize)> = RefCell::new((String::new(), 0));
+
+    /// The number of entries of each span callsite since the last sampled one.
+    static SAMPLE_COUNTERS: RefCell<HashMap<Identifier, u32>> = RefCell::new(HashMap::new());
+
+    /// Whether the current thread has been named in Tracy by a layer.
+    static THREAD_NAMED: Cell<bool> = Cell::new(false);
 }
 
 /// A tracing layer that collects data in Tracy profiling format.
 #[derive(Clone)]
 pub struct TracyLayer {
+    id: u32,
     stack_depth: u16,
+    parent_fields: usize,
+    event_truncation: Truncation,
+    busy_duration_plot: bool,
+    busy_summary_zone: bool,
+    sample_rate: Option<fn(&Metadata<'_>) -> u32>,
+    coalesce_reentries: bool,
+    zone_value: Option<fn(&Metadata<'_>) -> Option<u64>>,
+    zone_name: Option<ZoneNameFn>,
+    zone_name_truncation: Truncation,
+    dedupe_events: bool,
+    auto_thread_names: bool,
+    target_as_text: bool,
+    min_level: LevelFilter,
 }
 
+/// See [`TracyLayer::with_zone_name`](TracyLayer::with_zone_name).
+type ZoneNameFn = fn(&Metadata<'_>, &str, Option<&Metadata<'_>>) -> String;
+
 impl TracyLayer {
     /// Create a new `TracyLayer`.
     ///
     /// Defaults to collecting stack traces.
     pub fn new() -> Self {
-        Self { stack_depth: 64 }
+        static NEXT_ID: AtomicU32 = AtomicU32::new(0);
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            stack_depth: 64,
+            parent_fields: 0,
+            event_truncation: Truncation::Head,
+            busy_duration_plot: false,
+            busy_summary_zone: false,
+            sample_rate: None,
+            coalesce_reentries: false,
+            zone_value: None,
+            zone_name: None,
+            zone_name_truncation: Truncation::Head,
+            dedupe_events: false,
+            auto_thread_names: false,
+            target_as_text: false,
+            min_level: LevelFilter::TRACE,
+        }
+    }
+
+    /// A number identifying this layer.
+    ///
+    /// Every layer created with [`new`](TracyLayer::new) gets a distinct id, which prefixes the
+    /// warnings the layer emits as Tracy messages. This tells the layers apart when several of
+    /// them are active. Clones of a layer share its id.
+    pub fn id(&self) -> u32 {
+        self.id
     }
 
     /// Specify the maximum number of stack frames that will be collected.
@@ -72,6 +271,370 @@ impl TracyLayer {
         self.stack_depth = stack_depth;
         self
     }
+
+    /// Attach the fields of up to `max_ancestors` parent spans as the text of each zone.
+    ///
+    /// This is useful when the informative fields are recorded on an outer span, but the zones of
+    /// interest are its children. Enabling this requires the layer to format and store the fields
+    /// of every span, and to walk the span's ancestors every time it is entered, so keep the
+    /// number small for hot spans.
+    ///
+    /// Specifying 0 ancestors (the default) disables this.
+    pub fn with_parent_fields(mut self, max_ancestors: usize) -> Self {
+        self.parent_fields = max_ancestors;
+        self
+    }
+
+    /// Specify how to shorten the messages of events that are too long for Tracy.
+    ///
+    /// Defaults to [`Truncation::Head`](Truncation::Head).
+    pub fn with_event_truncation(mut self, event_truncation: Truncation) -> Self {
+        self.event_truncation = event_truncation;
+        self
+    }
+
+    /// Specify how to shorten the names of zones that are too long for Tracy.
+    ///
+    /// Only names produced by a [`with_zone_name`](TracyLayer::with_zone_name) hook or given to
+    /// the spans at runtime are likely to exceed the limit. With
+    /// [`Truncation::Hash`](Truncation::Hash), names that only differ past the truncation point
+    /// still show up as distinct zones.
+    ///
+    /// Defaults to [`Truncation::Head`](Truncation::Head).
+    pub fn with_zone_name_truncation(mut self, zone_name_truncation: Truncation) -> Self {
+        self.zone_name_truncation = zone_name_truncation;
+        self
+    }
+
+    /// Plot the time each span has spent entered once the span is closed.
+    ///
+    /// The durations are added, in nanoseconds, to a plot named after the span. Spans that were
+    /// never entered are not plotted.
+    ///
+    /// This is disabled by default.
+    pub fn with_busy_duration_plot(mut self, enabled: bool) -> Self {
+        self.busy_duration_plot = enabled;
+        self
+    }
+
+    /// Emit a summary zone with the time each span has spent entered once the span is closed.
+    ///
+    /// The zone is named after the span, with a ` (busy)` suffix, and carries the total time, in
+    /// microseconds, as its value. It is emitted on the thread that closes the span. This gives
+    /// the total of spans entered many times, such as the ones instrumenting futures, at a
+    /// glance. Spans that were never entered get no summary zone.
+    ///
+    /// This is disabled by default.
+    pub fn with_busy_summary_zone(mut self, enabled: bool) -> Self {
+        self.busy_summary_zone = enabled;
+        self
+    }
+
+    /// Only create a zone for one in every `sample_rate(metadata)` entries of a span.
+    ///
+    /// Tracy cannot drop a zone once it has been started, so spans that are entered very often
+    /// but only for a short time cannot be filtered by their duration. Instead, this thins them
+    /// out: the zones that remain are representative of the span, but their count and total
+    /// duration are only an approximation, scaled down by the sample rate. The entries are
+    /// counted separately for each callsite and thread, and the first entry is always recorded.
+    ///
+    /// A rate of 0 or 1 records every entry, which is also the default for all spans.
+    pub fn with_sample_rate(mut self, sample_rate: fn(&Metadata<'_>) -> u32) -> Self {
+        self.sample_rate = Some(sample_rate);
+        self
+    }
+
+    /// Record a span that is entered again while it is the innermost entered span as one zone.
+    ///
+    /// By default every entry of a span starts a new zone, so a recursively entered span shows up
+    /// as nested zones with the same name. With this enabled, the zone only ends once the
+    /// outermost entry exits. Re-entries of a span that is not the innermost entered span on the
+    /// thread still start a new zone, as Tracy zones have to be properly nested.
+    ///
+    /// This is disabled by default.
+    pub fn with_coalesce_reentries(mut self, enabled: bool) -> Self {
+        self.coalesce_reentries = enabled;
+        self
+    }
+
+    /// Set the value of each zone to `zone_value(metadata)` of its span.
+    ///
+    /// This allows encoding e.g. the level of the span as a number that can be filtered on in
+    /// Tracy. Zones for which `zone_value` returns `None` get no value, which is also the default
+    /// for all zones.
+    pub fn with_zone_value(mut self, zone_value: fn(&Metadata<'_>) -> Option<u64>) -> Self {
+        self.zone_value = Some(zone_value);
+        self
+    }
+
+    /// Name the zones with `zone_name(metadata, fields, parent)` instead of the span name.
+    ///
+    /// `fields` are the fields of the span, formatted the same way as for the events, and
+    /// `parent` is the metadata of the span's parent, if any. This allows e.g. qualifying the
+    /// names of generic spans with the name of their parent. Enabling this requires the layer to
+    /// format and store the fields of every span, and `zone_name` is called on every entry, so it
+    /// should be cheap.
+    ///
+    /// By default the zones are named after their span.
+    pub fn with_zone_name(
+        mut self,
+        zone_name: fn(&Metadata<'_>, &str, Option<&Metadata<'_>>) -> String,
+    ) -> Self {
+        self.zone_name = Some(zone_name);
+        self
+    }
+
+    /// Only emit the first of consecutive identical event messages on a thread.
+    ///
+    /// Tracy's message list struggles with large numbers of messages, which code emitting the same
+    /// event over and over, e.g. in a polling loop, quickly produces. With this enabled, repeats
+    /// are suppressed, and once a different event arrives on the same thread, a message with the
+    /// number of suppressed repeats is emitted ahead of it. Repeats at the very end of a thread's
+    /// life are not reported. Frame marks and plot points of repeated events are still emitted.
+    ///
+    /// This is disabled by default.
+    pub fn with_dedupe_events(mut self, enabled: bool) -> Self {
+        self.dedupe_events = enabled;
+        self
+    }
+
+    /// Attach the target of each span, usually its module path, to its zones as text.
+    ///
+    /// This keeps the origin of zones with short names visible in the zone's details.
+    ///
+    /// This is disabled by default.
+    pub fn with_target_as_text(mut self, enabled: bool) -> Self {
+        self.target_as_text = enabled;
+        self
+    }
+
+    /// Name the threads in Tracy after their standard library names.
+    ///
+    /// With this enabled, the first span entered or event emitted on a thread sets the Tracy name
+    /// of the thread to [the name it was spawned with](std::thread::Builder::name), if any. This
+    /// saves naming every spawned thread with
+    /// [`tracy_client::set_thread_name`](tracy_client::set_thread_name) by hand.
+    ///
+    /// This is disabled by default.
+    pub fn with_auto_thread_names(mut self, enabled: bool) -> Self {
+        self.auto_thread_names = enabled;
+        self
+    }
+
+    /// Only create zones for spans at `min_level` or above.
+    ///
+    /// Spans below the level are ignored by this layer even if the subscriber enables them, for
+    /// example so that `TRACE` spans are still logged by other layers, but don't clutter the
+    /// Tracy timeline.
+    ///
+    /// All spans are profiled by default.
+    pub fn with_min_level(mut self, min_level: LevelFilter) -> Self {
+        self.min_level = min_level;
+        self
+    }
+
+    /// The minimum level of spans that get zones, see
+    /// [`with_min_level`](TracyLayer::with_min_level).
+    pub fn min_level(&self) -> LevelFilter {
+        self.min_level
+    }
+
+    /// Name the current thread in Tracy, unless it already has been.
+    fn name_thread(&self) {
+        if !self.auto_thread_names || THREAD_NAMED.with(|named| named.replace(true)) {
+            return;
+        }
+        if let Some(name) = std::thread::current().name() {
+            set_thread_name(name);
+        }
+    }
+
+    /// Emit a warning about the layer's operation as a Tracy message.
+    fn warn(&self, text: &str) {
+        let text = format!("TracyLayer #{}: {}", self.id, text);
+        color_message(&text, 0xFF000000, self.stack_depth);
+    }
+
+    /// Whether `text` repeats the previous event message on this thread.
+    ///
+    /// Reports the number of repeats once a run of repeated messages ends.
+    fn repeated(&self, text: &str) -> bool {
+        LAST_MESSAGE.with(|last| {
+            let mut last = last.borrow_mut();
+            let (last_text, repeats) = &mut *last;
+            if last_text == text {
+                *repeats += 1;
+                return true;
+            }
+            if *repeats != 0 {
+                message(
+                    &format!("The previous message was repeated {} more times", repeats),
+                    self.stack_depth,
+                );
+            }
+            last_text.clear();
+            last_text.push_str(text);
+            *repeats = 0;
+            false
+        })
+    }
+
+    /// Whether the fields of spans need to be formatted and stored.
+    fn formats_fields(&self) -> bool {
+        self.parent_fields != 0 || self.zone_name.is_some()
+    }
+
+    /// Pass each text to attach to the zone of an entry of `span_data` to `emit`.
+    fn zone_texts<'a, S>(&self, span_data: &registry::SpanRef<'a, S>, mut emit: impl FnMut(&str))
+    where
+        S: registry::LookupSpan<'a>,
+    {
+        if self.target_as_text {
+            emit(truncate(span_data.metadata().target(), MAX_MESSAGE_LEN));
+        }
+        if let Some(OtelSpanId(span_id)) = span_data.extensions().get::<OtelSpanId>() {
+            emit(span_id);
+        }
+        if self.parent_fields != 0 {
+            let mut text = String::new();
+            let ancestors = std::iter::successors(span_data.parent(), |p| p.parent());
+            for parent in ancestors.take(self.parent_fields) {
+                if let Some(fields) = parent.extensions().get::<FormattedFields>() {
+                    if fields.0.is_empty() {
+                        continue;
+                    }
+                    if !text.is_empty() {
+                        text.push_str(", ");
+                    }
+                    text.push_str(&fields.0);
+                }
+            }
+            if !text.is_empty() {
+                emit(truncate(&text, MAX_MESSAGE_LEN));
+            }
+        }
+    }
+
+    /// The maximum number of stack frames to collect for the zones of `span_data`.
+    fn stack_depth<'a, S>(&self, span_data: &registry::SpanRef<'a, S>) -> u16
+    where
+        S: registry::LookupSpan<'a>,
+    {
+        match span_data.extensions().get::<SpanStackDepth>() {
+            Some(SpanStackDepth(depth)) => *depth,
+            None => self.stack_depth,
+        }
+    }
+
+    /// The plot and the point to add to it for the busy time of `span_data` as it closes.
+    fn busy_duration_point<'a, S>(
+        &self,
+        span_data: &registry::SpanRef<'a, S>,
+    ) -> Option<(&'static str, f64)>
+    where
+        S: registry::LookupSpan<'a>,
+    {
+        if !self.busy_duration_plot {
+            return None;
+        }
+        let extensions = span_data.extensions();
+        let busy = extensions.get::<BusyTime>().filter(|busy| busy.entered)?;
+        Some((intern(span_data.metadata().name()), busy.total.as_nanos() as f64))
+    }
+
+    /// The name and value of the zone summarizing the busy time of `span_data` as it closes.
+    fn busy_summary_zone<'a, S>(
+        &self,
+        span_data: &registry::SpanRef<'a, S>,
+    ) -> Option<(String, u64)>
+    where
+        S: registry::LookupSpan<'a>,
+    {
+        if !self.busy_summary_zone {
+            return None;
+        }
+        let extensions = span_data.extensions();
+        let busy = extensions.get::<BusyTime>().filter(|busy| busy.entered)?;
+        Some((format!("{} (busy)", span_data.metadata().name()), busy.micros()))
+    }
+
+    /// The frame set to mark the end of a frame of as `span_data` closes.
+    fn frame_boundary<'a, S>(&self, span_data: &registry::SpanRef<'a, S>) -> Option<&'static str>
+    where
+        S: registry::LookupSpan<'a>,
+    {
+        span_data.extensions().get::<FrameBoundary>().map(|FrameBoundary(name)| *name)
+    }
+
+    /// Whether an entry of a span with this metadata should have a zone.
+    fn sampled(&self, metadata: &'static Metadata<'static>) -> bool {
+        if *metadata.level() > self.min_level {
+            return false;
+        }
+        let rate = match self.sample_rate {
+            Some(sample_rate) => sample_rate(metadata),
+            None => return true,
+        };
+        if rate <= 1 {
+            return true;
+        }
+        SAMPLE_COUNTERS.with(|counters| {
+            let mut counters = counters.borrow_mut();
+            let counter = counters.entry(metadata.callsite()).or_insert(0);
+            let sampled = *counter == 0;
+            *counter = (*counter + 1) % rate;
+            sampled
+        })
+    }
+}
+
+/// How to shorten event messages and zone names that are longer than Tracy supports.
+///
+/// For event messages, a warning is emitted after the shortened message in each case.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Truncation {
+    /// Keep the beginning of the text.
+    Head,
+    /// Keep the beginning and the end of the text, replacing the middle with `…`.
+    HeadTail,
+    /// Keep the beginning of the text, followed by a hash of the entire text.
+    ///
+    /// This keeps texts that only differ past the truncation point distinguishable.
+    Hash,
+}
+
+impl Truncation {
+    /// Shorten `text` to at most `max_len` bytes.
+    fn apply(self, text: &str, max_len: usize) -> Cow<'_, str> {
+        if text.len() <= max_len {
+            return Cow::Borrowed(text);
+        }
+        match self {
+            Truncation::Head => Cow::Borrowed(truncate(text, max_len)),
+            Truncation::HeadTail if max_len < "…".len() => {
+                Cow::Borrowed(truncate(text, max_len))
+            }
+            Truncation::HeadTail => {
+                const SEPARATOR: &str = "…";
+                let head = truncate(text, (max_len - SEPARATOR.len()) / 2);
+                let mut tail_start = text.len() - (max_len - SEPARATOR.len() - head.len());
+                while !text.is_char_boundary(tail_start) {
+                    tail_start += 1;
+                }
+                Cow::Owned(format!("{}{}{}", head, SEPARATOR, &text[tail_start..]))
+            }
+            Truncation::Hash => {
+                let mut hasher = DefaultHasher::new();
+                text.hash(&mut hasher);
+                let suffix = format!("… [{:016x}]", hasher.finish());
+                if max_len < suffix.len() {
+                    return Cow::Borrowed(truncate(text, max_len));
+                }
+                let head = truncate(text, max_len - suffix.len());
+                Cow::Owned(format!("{}{}", head, suffix))
+            }
+        }
+    }
 }
 
 impl Default for TracyLayer {
@@ -84,79 +647,411 @@ impl<S> Layer<S> for TracyLayer
 where
     S: Subscriber + for<'a> registry::LookupSpan<'a>,
 {
+    fn new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let span_data = match ctx.span(id) {
+            Some(span_data) => span_data,
+            None => return,
+        };
+        if *attrs.metadata().level() > self.min_level {
+            return;
+        }
+        let mut extensions = span_data.extensions_mut();
+        let fields = attrs.metadata().fields();
+        let recognized = fields.iter().any(|field| is_recognized(field.name()));
+        if self.formats_fields() || recognized {
+            let mut visitor = TracyEventFieldVisitor::new();
+            attrs.record(&mut visitor);
+            if let Some(name) = visitor.frame_boundary {
+                extensions.insert(FrameBoundary(name));
+            }
+            if let Some(depth) = visitor.stack_depth {
+                extensions.insert(SpanStackDepth(depth));
+            }
+            if let Some(span_id) = visitor.otel_span_id.take() {
+                extensions.insert(OtelSpanId(span_id));
+            }
+            if self.formats_fields() {
+                extensions.insert(FormattedFields(visitor.dest));
+            }
+        }
+        if self.busy_duration_plot || self.busy_summary_zone {
+            extensions.insert(BusyTime::default());
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span_data) = ctx.span(id) {
+            let fields = span_data.metadata().fields();
+            let otel_span_id = fields.field(OTEL_SPAN_ID).map_or(false, |f| values.contains(&f));
+            if !self.formats_fields() && !otel_span_id {
+                return;
+            }
+            let mut extensions = span_data.extensions_mut();
+            let mut visitor = TracyEventFieldVisitor::new();
+            values.record(&mut visitor);
+            if let Some(span_id) = visitor.otel_span_id.take() {
+                extensions.insert(OtelSpanId(span_id));
+            }
+            if let Some(fields) = extensions.get_mut::<FormattedFields>() {
+                if !visitor.first {
+                    if !fields.0.is_empty() {
+                        fields.0.push_str(", ");
+                    }
+                    fields.0.push_str(&visitor.dest);
+                }
+            }
+        }
+    }
+
     fn on_enter(&self, id: &Id, ctx: Context<S>) {
+        self.name_thread();
         if let Some(span_data) = ctx.span(id) {
+            if let Some(busy) = span_data.extensions_mut().get_mut::<BusyTime>() {
+                busy.enter();
+            }
+            if self.coalesce_reentries {
+                let reentered = TRACY_SPAN_STACK.with(|s| match s.borrow_mut().back_mut() {
+                    Some((_, span_id, reentries)) if *span_id == id.into_u64() => {
+                        *reentries += 1;
+                        true
+                    }
+                    _ => false,
+                });
+                if reentered {
+                    return;
+                }
+            }
             let metadata = span_data.metadata();
-            let file = metadata.file().unwrap_or("<error: not available>");
-            let line = metadata.line().unwrap_or(0);
+            let span = if self.sampled(metadata) {
+                let file = metadata.file().unwrap_or("<error: not available>");
+                let file = truncate(file, MAX_ZONE_NAME_LEN);
+                let name = match self.zone_name {
+                    Some(zone_name) => {
+                        let extensions = span_data.extensions();
+                        let fields = extensions.get::<FormattedFields>().map_or("", |f| &f.0);
+                        let parent = span_data.parent().map(|parent| parent.metadata());
+                        Cow::Owned(zone_name(metadata, fields, parent))
+                    }
+                    None => Cow::Borrowed(metadata.name()),
+                };
+                let name = self.zone_name_truncation.apply(&name, MAX_ZONE_NAME_LEN - file.len());
+                let line = metadata.line().unwrap_or(0);
+                let span = Span::new(&name, "", file, line, self.stack_depth(&span_data));
+                if let Some(value) = self.zone_value.and_then(|zone_value| zone_value(metadata)) {
+                    span.emit_value(value);
+                }
+                self.zone_texts(&span_data, |text| span.emit_text(text));
+                Some(span)
+            } else {
+                None
+            };
             TRACY_SPAN_STACK.with(|s| {
-                s.borrow_mut().push_back((
-                    Span::new(metadata.name(), "", file, line, self.stack_depth),
-                    id.into_u64()
-                ));
+                s.borrow_mut().push_back((span, id.into_u64(), 0));
             });
         }
     }
 
-    fn on_exit(&self, id: &Id, _: Context<S>) {
+    fn on_exit(&self, id: &Id, ctx: Context<S>) {
+        if let Some(span_data) = ctx.span(id) {
+            if let Some(busy) = span_data.extensions_mut().get_mut::<BusyTime>() {
+                busy.exit();
+            }
+        }
         TRACY_SPAN_STACK.with(|s| {
-            if let Some((span, span_id)) = s.borrow_mut().pop_back() {
+            let mut stack = s.borrow_mut();
+            if let Some((_, span_id, reentries)) = stack.back_mut() {
+                if *reentries != 0 && *span_id == id.into_u64() {
+                    *reentries -= 1;
+                    return;
+                }
+            }
+            if let Some((span, span_id, _)) = stack.pop_back() {
                 if id.into_u64() != span_id {
-                    color_message(
+                    self.warn(
                         "Tracing spans exited out of order! \
                         Trace may not be accurate for this span stack.",
-                        0xFF000000,
-                        self.stack_depth,
                     );
                 }
                 drop(span);
             } else {
-                color_message(
-                    "Exiting a tracing span, but got nothing on the tracy span stack!",
-                    0xFF000000,
-                    self.stack_depth,
-                );
+                self.warn("Exiting a tracing span, but got nothing on the tracy span stack!");
             }
         });
     }
 
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        if let Some(span_data) = ctx.span(&id) {
+            if let Some((name, value)) = self.busy_duration_point(&span_data) {
+                // SAFE: `intern` returns null-terminated strings.
+                unsafe { Plot::new_unchecked(name) }.point(value);
+            }
+            if let Some((name, value)) = self.busy_summary_zone(&span_data) {
+                let metadata = span_data.metadata();
+                let file = metadata.file().unwrap_or("<error: not available>");
+                let file = truncate(file, MAX_ZONE_NAME_LEN);
+                let name = truncate(&name, MAX_ZONE_NAME_LEN - file.len());
+                let line = metadata.line().unwrap_or(0);
+                let span = Span::new(name, "", file, line, 0);
+                span.emit_value(value);
+            }
+            if let Some(name) = self.frame_boundary(&span_data) {
+                // SAFE: `intern` returns null-terminated strings.
+                unsafe { finish_continuous_frame(name.as_ptr()) };
+            }
+        }
+    }
+
     fn on_event(&self, event: &Event, _: Context<'_, S>) {
-        let mut visitor = TracyEventFieldVisitor {
-            dest: String::new(),
-            first: true,
-            frame_mark: false,
-        };
+        self.name_thread();
+        let mut visitor = TracyEventFieldVisitor::new();
         event.record(&mut visitor);
-        if !visitor.first {
-            let mut max_len = usize::from(u16::max_value()) - 1;
-            if visitor.dest.len() >= max_len {
-                while !visitor.dest.is_char_boundary(max_len) {
-                    max_len -= 1;
-                }
-                message(&visitor.dest[..max_len], self.stack_depth);
-                color_message(
-                    "Message for the previous event was too long, truncated",
-                    0xFF000000,
-                    self.stack_depth,
-                );
-            } else {
-                message(&visitor.dest, self.stack_depth);
+        if visitor.as_zone {
+            let metadata = event.metadata();
+            let file = metadata.file().unwrap_or("<error: not available>");
+            let file = truncate(file, MAX_ZONE_NAME_LEN);
+            let name = truncate(metadata.name(), MAX_ZONE_NAME_LEN - file.len());
+            let line = metadata.line().unwrap_or(0);
+            let span = Span::new(name, "", file, line, self.stack_depth);
+            if !visitor.first {
+                span.emit_text(truncate(&visitor.dest, MAX_MESSAGE_LEN));
+            }
+        } else if !visitor.first && (!self.dedupe_events || !self.repeated(&visitor.dest)) {
+            let text = self.event_truncation.apply(&visitor.dest, MAX_MESSAGE_LEN);
+            message(&text, self.stack_depth);
+            if text.len() != visitor.dest.len() {
+                self.warn("Message for the previous event was too long, truncated");
             }
         }
         if visitor.frame_mark {
             finish_continuous_frame!();
         }
+        if let (Some(name), Some(value)) = (visitor.plot, visitor.plot_value) {
+            // SAFE: `intern` returns null-terminated strings.
+            unsafe { Plot::new_unchecked(name) }.point(value);
+        }
+    }
+}
+
+/// A field formatter for the `fmt` layer of `tracing-subscriber` that hides the fields reserved for
+/// [`TracyLayer`](TracyLayer).
+///
+/// The remaining fields are formatted with the wrapped formatter, by default the
+/// `DefaultFields` of `tracing-subscriber`.
+#[cfg(feature = "fmt")]
+#[derive(Clone, Debug)]
+pub struct HideReservedFields<F = tracing_subscriber::fmt::format::DefaultFields>(F);
+
+#[cfg(feature = "fmt")]
+impl Default for HideReservedFields {
+    fn default() -> Self {
+        Self(Default::default())
+    }
+}
+
+#[cfg(feature = "fmt")]
+impl<F> HideReservedFields<F> {
+    /// Hide the reserved fields from the fields formatted by `inner`.
+    pub fn new(inner: F) -> Self {
+        Self(inner)
+    }
+}
+
+#[cfg(feature = "fmt")]
+impl<T, F: tracing_subscriber::field::MakeVisitor<T>> tracing_subscriber::field::MakeVisitor<T>
+    for HideReservedFields<F>
+{
+    type Visitor = HideReservedVisitor<F::Visitor>;
+
+    fn make_visitor(&self, target: T) -> Self::Visitor {
+        HideReservedVisitor(self.0.make_visitor(target))
+    }
+}
+
+/// The visitor produced by [`HideReservedFields`](HideReservedFields).
+#[cfg(feature = "fmt")]
+pub struct HideReservedVisitor<V>(V);
+
+#[cfg(feature = "fmt")]
+impl<V: Visit> Visit for HideReservedVisitor<V> {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if !is_reserved(field.name()) {
+            self.0.record_debug(field, value)
+        }
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        if !is_reserved(field.name()) {
+            self.0.record_i64(field, value)
+        }
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        if !is_reserved(field.name()) {
+            self.0.record_u64(field, value)
+        }
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        if !is_reserved(field.name()) {
+            self.0.record_bool(field, value)
+        }
     }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if !is_reserved(field.name()) {
+            self.0.record_str(field, value)
+        }
+    }
+}
+
+#[cfg(feature = "fmt")]
+impl<O, V: tracing_subscriber::field::VisitOutput<O>> tracing_subscriber::field::VisitOutput<O>
+    for HideReservedVisitor<V>
+{
+    fn finish(self) -> O {
+        self.0.finish()
+    }
+}
+
+#[cfg(feature = "fmt")]
+impl<V: tracing_subscriber::field::VisitFmt> tracing_subscriber::field::VisitFmt
+    for HideReservedVisitor<V>
+{
+    fn writer(&mut self) -> &mut dyn std::fmt::Write {
+        self.0.writer()
+    }
+}
+
+/// Get a `'static`, null-terminated copy of `name`.
+///
+/// Each distinct name is leaked only once, shared with the other users of `tracy-client`. Names
+/// are cut off at the first null byte, as Tracy would do anyway.
+fn intern(name: &str) -> &'static str {
+    match intern_name(name) {
+        Ok(interned) => interned,
+        Err(error) => intern(&name[..error.nul_position()]),
+    }
+}
+
+/// The time a span has spent entered.
+#[derive(Default)]
+struct BusyTime {
+    total: Duration,
+    entered: bool,
+    depth: usize,
+    since: Option<Instant>,
+}
+
+impl BusyTime {
+    fn enter(&mut self) {
+        self.entered = true;
+        self.depth += 1;
+        if self.depth == 1 {
+            self.since = Some(Instant::now());
+        }
+    }
+
+    fn exit(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+        if self.depth == 0 {
+            if let Some(since) = self.since.take() {
+                self.total += since.elapsed();
+            }
+        }
+    }
+
+    /// The total time spent entered, in microseconds.
+    fn micros(&self) -> u64 {
+        self.total.as_micros() as u64
+    }
+}
+
+/// Fields of a span, formatted for display in Tracy.
+struct FormattedFields(String);
+
+/// The name of the frame set the span marks a frame of when it is closed.
+struct FrameBoundary(&'static str);
+
+/// The maximum number of stack frames to collect for the span, overriding the layer's.
+struct SpanStackDepth(u16);
+
+/// The OpenTelemetry span id recorded for the span.
+struct OtelSpanId(String);
+
+/// Name of the field holding the OpenTelemetry span id.
+const OTEL_SPAN_ID: &str = "otel.span_id";
+
+/// Whether the field with this name is interpreted by this layer when creating a span.
+fn is_recognized(name: &str) -> bool {
+    is_reserved(name) || name == OTEL_SPAN_ID
+}
+
+/// Whether the field with this name is reserved for instructions to this layer.
+fn is_reserved(name: &str) -> bool {
+    name.starts_with("tracy.")
+}
+
+/// Truncate `text` to at most `max_len` bytes, respecting the character boundaries.
+fn truncate(text: &str, mut max_len: usize) -> &str {
+    if text.len() <= max_len {
+        return text;
+    }
+    while !text.is_char_boundary(max_len) {
+        max_len -= 1;
+    }
+    &text[..max_len]
 }
 
 struct TracyEventFieldVisitor {
     dest: String,
     frame_mark: bool,
     first: bool,
+    plot: Option<&'static str>,
+    plot_value: Option<f64>,
+    frame_boundary: Option<&'static str>,
+    stack_depth: Option<u16>,
+    otel_span_id: Option<String>,
+    as_zone: bool,
+}
+
+impl TracyEventFieldVisitor {
+    fn new() -> Self {
+        Self {
+            dest: String::new(),
+            first: true,
+            frame_mark: false,
+            plot: None,
+            plot_value: None,
+            frame_boundary: None,
+            stack_depth: None,
+            otel_span_id: None,
+            as_zone: false,
+        }
+    }
+
+    /// Render the `message` field ahead of all the other fields, without the `message = ` prefix.
+    fn record_message(&mut self, value: &dyn std::fmt::Display) {
+        if self.first {
+            let _ = write!(&mut self.dest, "{}", value);
+            self.first = false;
+        } else {
+            self.dest.insert_str(0, &format!("{}, ", value));
+        }
+    }
 }
 
 impl Visit for TracyEventFieldVisitor {
     fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            return self.record_message(&format_args!("{:?}", value));
+        }
+        if is_reserved(field.name()) {
+            // Reserved for instructions to this layer, which are not part of the message.
+            return;
+        }
+        if field.name() == OTEL_SPAN_ID && self.otel_span_id.is_none() {
+            self.otel_span_id = Some(format!("{:?}", value));
+        }
         // FIXME: this is a very crude formatter, but we don’t have
         // an easy way to do anything better...
         if self.first {
@@ -167,9 +1062,53 @@ impl Visit for TracyEventFieldVisitor {
         }
     }
 
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "message" => self.record_message(&value),
+            "tracy.plot" => self.plot = Some(intern(value)),
+            "tracy.frame_boundary" => self.frame_boundary = Some(intern(value)),
+            OTEL_SPAN_ID => {
+                self.otel_span_id = Some(value.to_owned());
+                self.record_debug(field, &value)
+            }
+            _ => self.record_debug(field, &value),
+        }
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        if field.name() == "value" {
+            self.plot_value = Some(value);
+        }
+        self.record_debug(field, &value)
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        match field.name() {
+            "value" => self.plot_value = Some(value as f64),
+            "tracy.stack_depth" => {
+                self.stack_depth = Some(value.max(0).min(u16::max_value().into()) as u16)
+            }
+            _ => {}
+        }
+        self.record_debug(field, &value)
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        match field.name() {
+            "value" => self.plot_value = Some(value as f64),
+            "tracy.stack_depth" => {
+                self.stack_depth = Some(value.min(u16::max_value().into()) as u16)
+            }
+            OTEL_SPAN_ID => self.otel_span_id = Some(format!("{:016x}", value)),
+            _ => {}
+        }
+        self.record_debug(field, &value)
+    }
+
     fn record_bool(&mut self, field: &Field, value: bool) {
         match (value, field.name()) {
             (true, "tracy.frame_mark") => self.frame_mark = true,
+            (true, "tracy.as_zone") => self.as_zone = true,
             _ => self.record_debug(field, &value),
         }
     }
@@ -225,6 +1164,28 @@ mod tests {
         });
     }
 
+    #[test]
+    fn coalesced_reentries() {
+        fn stack_len() -> usize {
+            super::TRACY_SPAN_STACK.with(|s| s.borrow().len())
+        }
+        let layer = super::TracyLayer::new().with_coalesce_reentries(true);
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            let span = span!(Level::INFO, "coalesced_reentries");
+            span.in_scope(|| {
+                span.in_scope(|| {
+                    span.in_scope(|| assert_eq!(stack_len(), 1));
+                    assert_eq!(stack_len(), 1);
+                    let other = span!(Level::INFO, "coalesced_reentries 2");
+                    other.in_scope(|| span.in_scope(|| assert_eq!(stack_len(), 3)));
+                });
+                assert_eq!(stack_len(), 1);
+            });
+            assert_eq!(stack_len(), 0);
+        });
+    }
+
     #[test]
     fn out_of_order() {
         setup_subscriber();
@@ -283,12 +1244,599 @@ mod tests {
         parent_task(5).await;
     }
 
+    /// Collect the results of visiting the fields of the events emitted by `f`.
+    fn visit_events(f: impl FnOnce()) -> Vec<super::TracyEventFieldVisitor> {
+        use std::sync::{Arc, Mutex};
+        use tracing_core::{Event, Subscriber};
+        use tracing_subscriber::layer::{Context, Layer};
+
+        struct Capture(Arc<Mutex<Vec<super::TracyEventFieldVisitor>>>);
+        impl<S: Subscriber> Layer<S> for Capture {
+            fn on_event(&self, event: &Event, _: Context<'_, S>) {
+                let mut visitor = super::TracyEventFieldVisitor::new();
+                event.record(&mut visitor);
+                self.0.lock().unwrap().push(visitor);
+            }
+        }
+
+        let captured = Arc::new(Mutex::new(Vec::new()));
+        let subscriber = tracing_subscriber::registry().with(Capture(captured.clone()));
+        tracing::subscriber::with_default(subscriber, f);
+        let mut captured = captured.lock().unwrap();
+        std::mem::take(&mut *captured)
+    }
+
+    /// The subscriber the spans inspected by `inspect_spans` are looked up in.
+    type Inspected =
+        tracing_subscriber::layer::Layered<super::TracyLayer, tracing_subscriber::Registry>;
+
+    /// When `inspect_spans` inspects a span.
+    #[derive(Clone, Copy, PartialEq)]
+    enum Inspect {
+        OnEnter,
+        OnClose,
+    }
+
+    /// Collect what `inspect` returns for the spans `f` enters or closes, right after `layer` has
+    /// handled the same notification.
+    fn inspect_spans<T, F>(layer: super::TracyLayer, when: Inspect, f: impl FnOnce(), inspect: F)
+        -> Vec<T>
+    where
+        T: Send + 'static,
+        F: Fn(&super::TracyLayer, &tracing_subscriber::registry::SpanRef<'_, Inspected>)
+            -> Option<T>
+            + Send
+            + Sync
+            + 'static,
+    {
+        use std::sync::{Arc, Mutex};
+        use tracing_core::span::Id;
+        use tracing_subscriber::layer::{Context, Layer};
+
+        struct Capture<T, F> {
+            layer: super::TracyLayer,
+            when: Inspect,
+            inspect: F,
+            captured: Arc<Mutex<Vec<T>>>,
+        }
+        impl<T, F> Capture<T, F>
+        where
+            F: Fn(&super::TracyLayer, &tracing_subscriber::registry::SpanRef<'_, Inspected>)
+                -> Option<T>,
+        {
+            fn capture(&self, when: Inspect, id: &Id, ctx: Context<'_, Inspected>) {
+                if when != self.when {
+                    return;
+                }
+                let span = ctx.span(id).unwrap();
+                if let Some(captured) = (self.inspect)(&self.layer, &span) {
+                    self.captured.lock().unwrap().push(captured);
+                }
+            }
+        }
+        impl<T, F> Layer<Inspected> for Capture<T, F>
+        where
+            T: Send + 'static,
+            F: Fn(&super::TracyLayer, &tracing_subscriber::registry::SpanRef<'_, Inspected>)
+                -> Option<T>
+                + Send
+                + Sync
+                + 'static,
+        {
+            fn on_enter(&self, id: &Id, ctx: Context<'_, Inspected>) {
+                self.capture(Inspect::OnEnter, id, ctx);
+            }
+
+            fn on_close(&self, id: Id, ctx: Context<'_, Inspected>) {
+                self.capture(Inspect::OnClose, &id, ctx);
+            }
+        }
+
+        let captured = Arc::new(Mutex::new(Vec::new()));
+        let capture = Capture { layer: layer.clone(), when, inspect, captured: captured.clone() };
+        let subscriber = tracing_subscriber::registry().with(layer).with(capture);
+        tracing::subscriber::with_default(subscriber, f);
+        let mut captured = captured.lock().unwrap();
+        std::mem::take(&mut *captured)
+    }
+
+    /// The names of the spans entered and the texts attached to their zones.
+    type ZoneTexts = Vec<(&'static str, Vec<String>)>;
+
+    /// Collect the texts `layer` attaches to the zones of the spans entered by `f`.
+    fn zone_texts(layer: super::TracyLayer, f: impl FnOnce()) -> ZoneTexts {
+        inspect_spans(layer, Inspect::OnEnter, f, |layer, span| {
+            let mut texts = Vec::new();
+            layer.zone_texts(span, |text| texts.push(text.to_string()));
+            Some((span.name(), texts))
+        })
+    }
+
+    #[test]
+    fn event_message_rendered_first() {
+        let visited = visit_events(|| {
+            info!(answer = 42, "the {} message", "formatted");
+            info!(message = "literal message", flag = true);
+            info!(answer = 42);
+        });
+        let messages = visited.iter().map(|v| &v.dest[..]).collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [
+                "the formatted message, answer = 42",
+                "literal message, flag = true",
+                "answer = 42",
+            ]
+        );
+    }
+
+    #[test]
+    fn plot_events() {
+        let visited = visit_events(|| {
+            event!(Level::INFO, tracy.plot = "fps", value = 60.0);
+            event!(Level::INFO, tracy.plot = "fps", value = 30);
+            info!(value = 1.0);
+        });
+        assert_eq!(visited[0].plot, Some("fps\0"));
+        assert_eq!(visited[0].plot_value, Some(60.0));
+        assert_eq!(visited[1].plot.map(str::as_ptr), visited[0].plot.map(str::as_ptr));
+        assert_eq!(visited[1].plot_value, Some(30.0));
+        assert_eq!(visited[2].plot, None);
+
+        setup_subscriber();
+        for i in 0..10 {
+            event!(Level::INFO, tracy.plot = "event plot", value = i);
+        }
+    }
+
+    #[test]
+    fn parent_fields() {
+        let layer = super::TracyLayer::new().with_parent_fields(2);
+        let texts = zone_texts(layer, || {
+            let parent = info_span!("parent", request = 42, path = "/index.html");
+            let _parent = parent.enter();
+            info_span!("child").in_scope(|| {
+                info_span!("grandchild", depth = 2).in_scope(|| {});
+            });
+            parent.record("request", 43);
+            info_span!("child after record").in_scope(|| {});
+        });
+        let parent = r#"request = 42, path = "/index.html""#;
+        assert_eq!(
+            texts,
+            [
+                ("parent", vec![]),
+                ("child", vec![parent.to_string()]),
+                ("grandchild", vec![parent.to_string()]),
+                ("child after record", vec![format!("{}, request = 43", parent)]),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "enable"))]
+    fn disabled_layer() {
+        let layer = super::TracyLayer::new().with_stackdepth(0).with_parent_fields(1);
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            info_span!("disabled", field = 1).in_scope(|| {
+                info!("disabled event");
+                event!(Level::INFO, tracy.plot = "disabled plot", value = 1.0);
+            });
+        });
+    }
+
+    #[test]
+    fn busy_duration_plot() {
+        let mut busy = super::BusyTime::default();
+        busy.enter();
+        busy.enter();
+        busy.exit();
+        assert!(busy.since.is_some());
+        busy.exit();
+        assert!(busy.entered);
+        assert!(busy.since.is_none());
+
+        let layer = super::TracyLayer::new().with_busy_duration_plot(true);
+        let spans = || {
+            for i in 0..10 {
+                info_span!("busy duration").in_scope(|| {
+                    std::thread::sleep(std::time::Duration::from_millis(i));
+                });
+            }
+            drop(info_span!("never entered"));
+        };
+        let points = inspect_spans(layer, Inspect::OnClose, spans, |layer, span| {
+            layer.busy_duration_point(span)
+        });
+        assert_eq!(points.len(), 10, "{:?}", points);
+        for (i, &(name, value)) in points.iter().enumerate() {
+            assert_eq!(name, "busy duration\0");
+            assert!(value >= (i as f64) * 1e6, "{:?}", points);
+        }
+    }
+
+    #[test]
+    fn busy_summary_zone() {
+        let spans = || {
+            let mut runtime = tokio::runtime::Runtime::new().unwrap();
+            runtime.block_on(parent_task(3));
+            drop(info_span!("never entered"));
+        };
+        let layer = super::TracyLayer::new().with_busy_summary_zone(true);
+        let start = std::time::Instant::now();
+        let summaries = inspect_spans(layer, Inspect::OnClose, spans, |layer, span| {
+            Some((span.name(), layer.busy_summary_zone(span)))
+        });
+        let elapsed = start.elapsed().as_micros() as u64;
+        let names = summaries.iter().map(|(_, zone)| zone.as_ref().map(|z| &z.0[..]));
+        let subtask = Some("subtask (busy)");
+        let parent = Some("parent_task (busy)");
+        assert_eq!(names.collect::<Vec<_>>(), [subtask, subtask, subtask, parent, None]);
+        assert_eq!(summaries.last().unwrap().0, "never entered");
+        // The subtasks mostly wait for their timers, which the busy time does not include.
+        for (_, zone) in summaries.iter().take(4) {
+            assert!(zone.as_ref().unwrap().1 < elapsed, "{:?}", summaries);
+        }
+
+        let layer = super::TracyLayer::new();
+        let summaries = inspect_spans(layer, Inspect::OnClose, spans, |layer, span| {
+            Some(layer.busy_summary_zone(span))
+        });
+        assert!(summaries.iter().all(Option::is_none), "{:?}", summaries);
+    }
+
+    #[test]
+    fn layer_ids() {
+        let first = super::TracyLayer::new();
+        let second = super::TracyLayer::new();
+        assert_ne!(first.id(), second.id());
+        assert_eq!(first.clone().with_stackdepth(0).id(), first.id());
+        first.warn("a warning from the first layer");
+        second.warn("a warning from the second layer");
+    }
+
+    #[test]
+    fn sample_rate() {
+        fn rate(metadata: &tracing_core::Metadata<'_>) -> u32 {
+            if metadata.name() == "hot" { 3 } else { 1 }
+        }
+        fn has_zone() -> bool {
+            super::TRACY_SPAN_STACK.with(|s| s.borrow().back().unwrap().0.is_some())
+        }
+        let layer = super::TracyLayer::new().with_sample_rate(rate);
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            let hot: Vec<bool> = (0..7).map(|_| info_span!("hot").in_scope(has_zone)).collect();
+            assert_eq!(hot, [true, false, false, true, false, false, true]);
+            assert!((0..3).all(|_| info_span!("cold").in_scope(has_zone)));
+        });
+    }
+
+    #[test]
+    fn min_level() {
+        fn has_zone() -> bool {
+            super::TRACY_SPAN_STACK.with(|s| s.borrow().back().unwrap().0.is_some())
+        }
+        let layer = super::TracyLayer::new()
+            .with_min_level(tracing_core::LevelFilter::DEBUG)
+            .with_busy_duration_plot(true);
+        assert_eq!(layer.min_level(), tracing_core::LevelFilter::DEBUG);
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            assert!(span!(Level::INFO, "info").in_scope(has_zone));
+            assert!(span!(Level::DEBUG, "debug").in_scope(has_zone));
+            let trace = span!(Level::TRACE, "trace");
+            assert!(!trace.in_scope(has_zone));
+            assert!(!span!(Level::DEBUG, "nested").in_scope(|| trace.in_scope(has_zone)));
+        });
+    }
+
+    #[test]
+    fn zone_value() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        fn level_value(metadata: &tracing_core::Metadata<'_>) -> Option<u64> {
+            CALLS.fetch_add(1, Ordering::Relaxed);
+            match *metadata.level() {
+                Level::ERROR => Some(1),
+                Level::WARN => Some(2),
+                _ => None,
+            }
+        }
+        let layer = super::TracyLayer::new().with_zone_value(level_value);
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            span!(Level::ERROR, "error").in_scope(|| {});
+            span!(Level::WARN, "warn").in_scope(|| {});
+            span!(Level::INFO, "info").in_scope(|| {});
+        });
+        assert_eq!(CALLS.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn zone_name() {
+        thread_local! {
+            static NAMES: std::cell::RefCell<Vec<String>> = Default::default();
+        }
+        fn qualified(
+            metadata: &tracing_core::Metadata<'_>,
+            fields: &str,
+            parent: Option<&tracing_core::Metadata<'_>>,
+        ) -> String {
+            let name = match parent {
+                Some(parent) => format!("{}::{} {{{}}}", parent.name(), metadata.name(), fields),
+                None => format!("{} {{{}}}", metadata.name(), fields),
+            };
+            NAMES.with(|names| names.borrow_mut().push(name.clone()));
+            name
+        }
+        let layer = super::TracyLayer::new().with_zone_name(qualified);
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            info_span!("parent").in_scope(|| {
+                let child = info_span!("child", id = 1, name = tracing::field::Empty);
+                child.record("name", "x");
+                child.in_scope(|| {});
+            });
+        });
+        let names = NAMES.with(|names| names.borrow().clone());
+        assert_eq!(names, ["parent {}", r#"parent::child {id = 1, name = "x"}"#]);
+    }
+
+    #[test]
+    fn reserved_fields() {
+        let visited = visit_events(|| {
+            info!(tracy.frame_mark = true);
+            info!(tracy.frame_mark = false, tracy.plot = "reserved", tracy.unknown = 1);
+            info!(tracy.plot = 5, answer = 42);
+        });
+        assert!(visited[0].first && visited[0].frame_mark);
+        assert!(visited[1].first && !visited[1].frame_mark);
+        assert_eq!(visited[1].plot, Some("reserved\0"));
+        assert_eq!(visited[2].dest, "answer = 42");
+    }
+
+    #[test]
+    fn events_as_zones() {
+        let visited = visit_events(|| {
+            info!(tracy.as_zone = true, bytes = 4096, "flushed the buffer");
+            info!(bytes = 4096, "flushed the buffer");
+        });
+        assert!(visited[0].as_zone && !visited[1].as_zone);
+        assert_eq!(visited[0].dest, visited[1].dest);
+
+        setup_subscriber();
+        info!(tracy.as_zone = true, bytes = 4096, "flushed the buffer");
+        info!(tracy.as_zone = true);
+        info!(tracy.as_zone = false, "shown as a message");
+    }
+
+    #[test]
+    fn frame_boundary() {
+        let visited = visit_events(|| info!(tracy.frame_boundary = "frames", other = 1));
+        assert_eq!(visited[0].frame_boundary, Some("frames\0"));
+        assert_eq!(visited[0].dest, "other = 1");
+
+        let spans = || {
+            for i in 0..3 {
+                let frame = info_span!("frame", tracy.frame_boundary = "frame boundary");
+                frame.in_scope(|| std::thread::sleep(std::time::Duration::from_millis(i)));
+            }
+            info_span!("not a frame").in_scope(|| {});
+        };
+        let marks = inspect_spans(super::TracyLayer::new(), Inspect::OnClose, spans, |layer, span| {
+            Some((span.name(), layer.frame_boundary(span)))
+        });
+        let frame = ("frame", Some("frame boundary\0"));
+        assert_eq!(marks, [frame, frame, frame, ("not a frame", None)]);
+    }
+
+    #[test]
+    fn dedupe_events() {
+        fn last_message() -> (String, usize) {
+            super::LAST_MESSAGE.with(|last| last.borrow().clone())
+        }
+        let layer = super::TracyLayer::new().with_dedupe_events(true);
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            for _ in 0..5 {
+                info!("poll returned pending");
+            }
+            assert_eq!(last_message(), ("poll returned pending".into(), 4));
+            info!("poll returned ready");
+            assert_eq!(last_message(), ("poll returned ready".into(), 0));
+            info!(tracy.frame_mark = true);
+            info!("poll returned ready");
+            assert_eq!(last_message(), ("poll returned ready".into(), 1));
+        });
+    }
+
+    #[test]
+    fn span_stack_depth() {
+        let spans = || {
+            info_span!("deep", tracy.stack_depth = 32).in_scope(|| {});
+            info_span!("clamped", tracy.stack_depth = 100_000).in_scope(|| {});
+            info_span!("negative", tracy.stack_depth = -1).in_scope(|| {});
+            info_span!("default").in_scope(|| {});
+        };
+        let layer = super::TracyLayer::new().with_stackdepth(8);
+        let depths = inspect_spans(layer, Inspect::OnEnter, spans, |layer, span| {
+            Some(layer.stack_depth(span))
+        });
+        assert_eq!(depths, [32, u16::max_value(), 0, 8]);
+    }
+
+    #[test]
+    fn otel_span_id() {
+        let texts = zone_texts(super::TracyLayer::new(), || {
+            info_span!("text", otel.span_id = "00f067aa0ba902b7").in_scope(|| {});
+            info_span!("number", otel.span_id = 0xf0_67aa_0ba9_02b7_u64).in_scope(|| {});
+            let recorded = info_span!("recorded", otel.span_id = tracing::field::Empty);
+            recorded.record("otel.span_id", "b7ad6b7169203331");
+            recorded.in_scope(|| {});
+            info_span!("none").in_scope(|| {});
+        });
+        let id = |id: &str| vec![id.to_string()];
+        assert_eq!(
+            texts,
+            [
+                ("text", id("00f067aa0ba902b7")),
+                ("number", id("00f067aa0ba902b7")),
+                ("recorded", id("b7ad6b7169203331")),
+                ("none", vec![]),
+            ]
+        );
+    }
+
     #[test]
     fn message_too_long() {
         setup_subscriber();
         info!("{}", "a".repeat(u16::max_value().into()));
     }
 
+    #[test]
+    fn event_truncation() {
+        use super::Truncation;
+        let message = format!("{}{}", "a".repeat(20), "é".repeat(20));
+        for strategy in &[Truncation::Head, Truncation::HeadTail, Truncation::Hash] {
+            assert_eq!(strategy.apply(&message, 100), message);
+            let shortened = strategy.apply(&message, 31);
+            assert!(shortened.len() <= 31, "{:?}: {}", strategy, shortened);
+        }
+        assert_eq!(Truncation::Head.apply(&message, 25), "aaaaaaaaaaaaaaaaaaaaéé");
+        assert_eq!(Truncation::HeadTail.apply(&message, 25), "aaaaaaaaaaa…ééééé");
+        let hashed = Truncation::Hash.apply(&message, 31);
+        assert!(hashed.starts_with("aaaaaaaaa… ["), "{}", hashed);
+        let other = format!("{}{}", "a".repeat(20), "ü".repeat(20));
+        assert_ne!(Truncation::Hash.apply(&other, 31), hashed);
+
+        for strategy in &[Truncation::Head, Truncation::HeadTail, Truncation::Hash] {
+            let layer = super::TracyLayer::new().with_event_truncation(*strategy);
+            let subscriber = tracing_subscriber::registry().with(layer);
+            tracing::subscriber::with_default(subscriber, || {
+                info!("{}", "a".repeat(u16::max_value().into()));
+            });
+        }
+    }
+
+    #[test]
+    fn auto_thread_names() {
+        fn named() -> bool {
+            super::THREAD_NAMED.with(|named| named.get())
+        }
+        let layer = super::TracyLayer::new().with_auto_thread_names(true);
+        let dispatch = tracing::Dispatch::new(tracing_subscriber::registry().with(layer));
+        let threads = [Some("worker"), None].iter().map(|name| {
+            let dispatch = dispatch.clone();
+            let mut builder = std::thread::Builder::new();
+            if let Some(name) = name {
+                builder = builder.name(name.to_string());
+            }
+            builder.spawn(move || {
+                tracing::dispatcher::with_default(&dispatch, || {
+                    assert!(!named());
+                    info_span!("first").in_scope(|| {});
+                    assert!(named());
+                    info!("second");
+                })
+            })
+        });
+        for thread in threads.collect::<Vec<_>>() {
+            thread.unwrap().join().unwrap();
+        }
+
+        let subscriber = tracing_subscriber::registry().with(super::TracyLayer::new());
+        std::thread::spawn(|| {
+            tracing::subscriber::with_default(subscriber, || info!("unnamed"));
+            assert!(!named());
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn target_as_text() {
+        let layer = super::TracyLayer::new().with_target_as_text(true);
+        let texts = zone_texts(layer, || {
+            info_span!("default target").in_scope(|| {});
+            info_span!(target: "custom::target", "custom target").in_scope(|| {});
+        });
+        assert_eq!(
+            texts,
+            [
+                ("default target", vec![module_path!().to_string()]),
+                ("custom target", vec!["custom::target".to_string()]),
+            ]
+        );
+        let texts = zone_texts(super::TracyLayer::new(), || info_span!("no text").in_scope(|| {}));
+        assert_eq!(texts, [("no text", vec![])]);
+    }
+
+    #[test]
+    #[cfg(feature = "fmt")]
+    fn reserved_fields_hidden_from_fmt() {
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Clone)]
+        struct Output(Arc<Mutex<Vec<u8>>>);
+        impl std::io::Write for Output {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let output = Output(Arc::new(Mutex::new(Vec::new())));
+        let writer = output.clone();
+        let fmt = tracing_subscriber::fmt::layer()
+            .with_writer(move || writer.clone())
+            .fmt_fields(super::HideReservedFields::default());
+        let subscriber = tracing_subscriber::registry().with(super::TracyLayer::new()).with(fmt);
+        tracing::subscriber::with_default(subscriber, || {
+            info!(tracy.frame_mark = true, frame = 1, "frame done");
+            info!(tracy.plot = "fps", value = 60);
+        });
+        let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+        assert!(lines[0].ends_with("frame done frame=1"), "{}", output);
+        assert!(lines[1].ends_with("value=60"), "{}", output);
+        assert!(!output.contains("tracy."), "{}", output);
+    }
+
+    #[test]
+    fn zone_name_truncation() {
+        use super::Truncation;
+        fn long_name(
+            metadata: &tracing_core::Metadata<'_>,
+            _: &str,
+            _: Option<&tracing_core::Metadata<'_>>,
+        ) -> String {
+            format!("{}{}", "n".repeat(u16::max_value().into()), metadata.name())
+        }
+        let first = format!("{}first", "n".repeat(u16::max_value().into()));
+        let second = format!("{}second", "n".repeat(u16::max_value().into()));
+        let max_len = super::MAX_ZONE_NAME_LEN - file!().len();
+        let head = Truncation::Head;
+        assert_eq!(head.apply(&first, max_len), head.apply(&second, max_len));
+        let hash = Truncation::Hash;
+        assert_ne!(hash.apply(&first, max_len), hash.apply(&second, max_len));
+        assert!(hash.apply(&first, max_len).len() <= max_len);
+        assert_eq!(hash.apply(&first, 3), "nnn");
+        assert_eq!(Truncation::HeadTail.apply(&first, 2), "nn");
+
+        let layer = super::TracyLayer::new()
+            .with_zone_name(long_name)
+            .with_zone_name_truncation(Truncation::Hash);
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            info_span!("first").in_scope(|| {});
+            info_span!("second").in_scope(|| {});
+        });
+    }
+
     #[test]
     fn long_span_data() {
         setup_subscriber();
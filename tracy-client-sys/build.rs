@@ -1,4 +1,11 @@
+use std::collections::HashSet;
+use std::ffi::OsString;
 use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// The version of the bundled Tracy client.
+#[cfg(feature = "pkg-config")]
+const TRACY_VERSION: &str = "0.7.8";
 
 fn link_libraries(target_os: &str) {
     match target_os {
@@ -28,15 +35,176 @@ fn set_feature_defines(mut c: cc::Build) -> cc::Build {
     c
 }
 
+/// Select the directory with the Tracy client sources to build.
+///
+/// A directory given with the `TRACY_CLIENT_SRC_DIR` environment variable takes precedence over
+/// the `tracy-submodule` directory selected with the feature of the same name, which takes
+/// precedence over the bundled sources in `tracy`.
+fn tracy_source_dir(src_dir: Option<OsString>, submodule: bool) -> Result<PathBuf, String> {
+    let dir = match src_dir {
+        Some(dir) if !dir.is_empty() => {
+            let dir = PathBuf::from(dir);
+            if !dir.is_absolute() {
+                return Err(format!(
+                    "TRACY_CLIENT_SRC_DIR must be an absolute path, got `{}`",
+                    dir.display()
+                ));
+            }
+            dir
+        }
+        _ if submodule => PathBuf::from("tracy-submodule"),
+        _ => PathBuf::from("tracy"),
+    };
+    if !dir.join("TracyClient.cpp").is_file() {
+        return Err(format!(
+            "`{}` does not contain the Tracy client sources (no TracyClient.cpp)",
+            dir.display()
+        ));
+    }
+    Ok(dir)
+}
+
+/// Ask cargo to re-run the build script whenever `path` or any file it includes changes.
+///
+/// Without this cargo only notices changes to the files it packages, which in practice means
+/// edits to the Tracy sources made while debugging would not cause a rebuild. Only the files
+/// reachable through `#include "..."` directives are listed, so that a full Tracy checkout given
+/// with `TRACY_CLIENT_SRC_DIR` does not have its server, profiler or `.git` files listed too.
+fn rerun_if_changed(path: &Path, seen: &mut HashSet<PathBuf>) -> std::io::Result<()> {
+    if !seen.insert(path.canonicalize()?) {
+        return Ok(());
+    }
+    println!("cargo:rerun-if-changed={}", path.display());
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    for line in std::fs::read_to_string(path)?.lines() {
+        if let Some(included) = quoted_include(line) {
+            let included = directory.join(included);
+            // Headers of other platforms or optional features may be missing from the sources.
+            if included.is_file() {
+                rerun_if_changed(&included, seen)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The path in an `#include "path"` directive, if `line` is one.
+fn quoted_include(line: &str) -> Option<&str> {
+    let line = line.trim_start();
+    if !line.starts_with('#') {
+        return None;
+    }
+    let line = line[1..].trim_start();
+    if !line.starts_with("include") {
+        return None;
+    }
+    let line = line["include".len()..].trim_start();
+    if !line.starts_with('"') {
+        return None;
+    }
+    let line = &line[1..];
+    line.find('"').map(|end| &line[..end])
+}
+
+/// Check that the C++ compiler lays out the structs shared with Rust the way the bindings expect.
+///
+/// The layout tests generated by bindgen only run with `cargo test` on the host, and only encode
+/// the layouts of 64-bit targets. This probe instead runs whenever the bundled client is built,
+/// for the actual target, and fails the build if the layouts of 32 or 64-bit targets differ
+/// from what the `#[repr(C)]` structs in `src/generated.rs` have on them.
+fn probe_layouts(source_dir: &Path) {
+    let pointer = match std::env::var("CARGO_CFG_TARGET_POINTER_WIDTH").ok().as_deref() {
+        Some("32") => 4,
+        Some("64") => 8,
+        width => panic!("unsupported pointer width: {:?}", width),
+    };
+    // Three pointers followed by two `u32`s need no padding with either pointer width.
+    let source_location = 3 * pointer + 4 + 4;
+    let probe = format!(
+        r#"#include <cstddef>
+#include "TracyC.h"
+#define LAYOUT(what, actual, expected) \
+    static_assert(actual == expected, "unexpected layout of " what ", see src/generated.rs")
+LAYOUT("___tracy_source_location_data", sizeof(___tracy_source_location_data), {size});
+LAYOUT("___tracy_source_location_data::function",
+       offsetof(___tracy_source_location_data, function), {pointer});
+LAYOUT("___tracy_source_location_data::file",
+       offsetof(___tracy_source_location_data, file), {file});
+LAYOUT("___tracy_source_location_data::line",
+       offsetof(___tracy_source_location_data, line), {line});
+LAYOUT("___tracy_source_location_data::color",
+       offsetof(___tracy_source_location_data, color), {color});
+LAYOUT("___tracy_c_zone_context", sizeof(___tracy_c_zone_context), 8);
+LAYOUT("___tracy_c_zone_context::active", offsetof(___tracy_c_zone_context, active), 4);
+"#,
+        size = source_location,
+        pointer = pointer,
+        file = 2 * pointer,
+        line = 3 * pointer,
+        color = 3 * pointer + 4,
+    );
+    let out_dir = std::env::var_os("OUT_DIR").expect("OUT_DIR is not set");
+    let path = Path::new(&out_dir).join("layout_probe.cpp");
+    std::fs::write(&path, probe).expect("could not write the layout probe");
+    // Only compiling the probe matters, it is not linked.
+    set_feature_defines(cc::Build::new())
+        .file(path)
+        .include(source_dir)
+        .cpp(true)
+        .cargo_metadata(false)
+        .compile("tracy-layout-probe");
+}
+
+/// Try to link a system-wide Tracy client library found with `pkg-config`.
+///
+/// Only the exact version of the bundled client is accepted, as other versions may not match the
+/// bindings or may speak an incompatible protocol.
+#[cfg(feature = "pkg-config")]
+fn link_system_library() -> bool {
+    match pkg_config::Config::new().exactly_version(TRACY_VERSION).probe("TracyClient") {
+        Ok(_) => true,
+        Err(e) => {
+            let e = e.to_string().replace('\n', " ");
+            println!("cargo:warning=using the bundled Tracy client: {}", e);
+            false
+        }
+    }
+}
+
+#[cfg(not(feature = "pkg-config"))]
+fn link_system_library() -> bool {
+    false
+}
 
 fn main() {
-    if std::env::var_os("CARGO_FEATURE_ENABLE").is_some() {
+    println!("cargo:rerun-if-changed=build.rs");
+    // This is the only variable read here that cargo does not set itself. The ones read by `cc`
+    // and `pkg-config` are reported by those crates.
+    println!("cargo:rerun-if-env-changed=TRACY_CLIENT_SRC_DIR");
+    if std::env::var_os("CARGO_FEATURE_ENABLE").is_some() && !link_system_library() {
+        if std::env::var("CARGO_CFG_TARGET_ARCH").ok().as_deref() == Some("wasm32") {
+            writeln!(::std::io::stderr(),
+                     "Tracy does not support wasm32, disable the `enable` feature for this target")
+                .expect("could not report the error");
+            ::std::process::exit(0xfd);
+        }
+        let source_dir = tracy_source_dir(
+            std::env::var_os("TRACY_CLIENT_SRC_DIR"),
+            std::env::var_os("CARGO_FEATURE_TRACY_SUBMODULE").is_some(),
+        )
+        .unwrap_or_else(|e| panic!("{}", e));
+        let mut seen = HashSet::new();
+        for file in &["TracyClient.cpp", "TracyC.h"] {
+            rerun_if_changed(&source_dir.join(file), &mut seen)
+                .expect("could not list the tracy sources");
+        }
         set_feature_defines(cc::Build::new())
-            .file("tracy/TracyClient.cpp")
+            .file(source_dir.join("TracyClient.cpp"))
             .warnings(false)
             .cpp(true)
             .flag_if_supported("-std=gnu++17")
             .compile("libtracy-client.a");
+        probe_layouts(&source_dir);
     }
 
     match std::env::var("CARGO_CFG_TARGET_OS") {
@@ -12,6 +12,13 @@
 //!
 //! As thus, you may want make sure to only enable the `tracy-client-sys` crate conditionally, via
 //! the `enable` feature flag provided by this crate.
+//!
+//! # Linking a system Tracy library
+//!
+//! By default the Tracy client library bundled with this crate is built and linked. With the
+//! `pkg-config` feature enabled, a system-wide `TracyClient` library is looked up with
+//! `pkg-config` first, and the bundled library is only built if no system library of the same
+//! version as the bundled one is found.
 #![allow(non_snake_case, non_camel_case_types, unused_variables)]
 
 #[cfg(feature="enable")]
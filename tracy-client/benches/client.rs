@@ -0,0 +1,36 @@
+#[macro_use]
+extern crate bencher;
+
+use bencher::Bencher;
+use tracy_client::{message, messages, Span};
+
+fn named_span(bench: &mut Bencher) {
+    bench.iter(|| Span::new("named span", "named_span", file!(), line!(), 0));
+}
+
+fn nameless_span(bench: &mut Bencher) {
+    bench.iter(|| Span::new("", "nameless_span", file!(), line!(), 0));
+}
+
+/// The lines of a diagnostic dump.
+fn dump() -> Vec<String> {
+    (0..100).map(|i| format!("  entity {} = {{ position: ({}, {}) }}", i, i * 2, i * 3)).collect()
+}
+
+fn message_per_line(bench: &mut Bencher) {
+    let lines = dump();
+    bench.iter(|| {
+        for line in &lines {
+            message(line, 0);
+        }
+    });
+}
+
+fn message_batch(bench: &mut Bencher) {
+    let lines = dump();
+    bench.iter(|| messages(&lines, 0));
+}
+
+benchmark_group!(spans, named_span, nameless_span);
+benchmark_group!(batches, message_per_line, message_batch);
+benchmark_main!(spans, batches);
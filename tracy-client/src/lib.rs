@@ -12,19 +12,116 @@
 //! As thus, you may want make sure to only enable the `tracy-client` crate conditionally, via the
 //! `enable` feature flag provided by this crate.
 //!
+//! # Optional features
+//!
+//! * `rgb` – allows using the colour types from the [rgb](https://docs.rs/rgb) crate as a
+//!   [`Color`](Color).
+//!
 //! [Tracy profiler]: https://github.com/wolfpld/tracy
 #![cfg_attr(not(feature="enable"), allow(unused_imports, unused_variables))]
 
 use std::alloc;
-use std::ffi::CString;
+use std::cell::{RefCell, UnsafeCell};
+use std::collections::hash_map::{DefaultHasher, HashMap};
+use std::hash::{Hash, Hasher};
+use std::ffi::{CStr, CString};
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU16, AtomicUsize, Ordering};
+use std::sync::{mpsc, Mutex, Once};
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::{Duration, Instant};
 use tracy_client_sys as sys;
 
+/// The maximum length of a message, a span text or a span name set after the span started, in
+/// bytes.
+///
+/// Longer data must be truncated before passing it to this crate.
+pub const MAX_MESSAGE_LEN: usize = std::u16::MAX as usize - 1;
+
+/// The maximum combined length of the name, function and file of a span started with
+/// [`Span::new`], in bytes.
+///
+/// Longer data must be truncated before passing it to this crate.
+pub const MAX_ZONE_NAME_LEN: usize = std::u16::MAX as usize - SOURCE_LOCATION_OVERHEAD;
+
+// These mirror the assertions in the bundled Tracy client library (v0.7.8) and must be reviewed
+// whenever the Tracy version changes. A source location allocated by Tracy takes up a 2 byte
+// size, a 4 byte colour, a 4 byte line number and the terminators of the function and file in
+// addition to the strings, and must fit in an `uint16_t`.
+const SOURCE_LOCATION_OVERHEAD: usize = 2 + 4 + 4 + 1 + 1;
+
 /// A handle representing a span of execution.
+///
+/// Tracy times spans as they happen: a span begins when it is created and ends when it is
+/// dropped. The Tracy version used by this crate has no way to record a zone with timestamps
+/// measured elsewhere, so timings from e.g. a trace file cannot be imported as spans. Such
+/// durations can be added to a [`Plot`](Plot), or to a span with [`emit_value`](Span::emit_value).
+#[cfg(feature="enable")]
+pub struct Span(sys::___tracy_c_zone_context, SpanThread);
+
+thread_local! {
+    /// The buffer `Span::emit_display` and `Span::emit_debug` format into.
+    static SCRATCH: RefCell<String> = RefCell::new(String::new());
+}
+
+/// Format `args` and pass the result, truncated to `MAX_MESSAGE_LEN`, to `f`.
+fn with_formatted(args: std::fmt::Arguments<'_>, f: impl FnOnce(&str)) {
+    use std::fmt::Write;
+    SCRATCH.with(|scratch| match scratch.try_borrow_mut() {
+        Ok(mut buffer) => {
+            buffer.clear();
+            let _ = buffer.write_fmt(args);
+            f(truncate(&buffer))
+        }
+        // The value being formatted emits text of its own.
+        Err(_) => f(truncate(&args.to_string())),
+    })
+}
+
+/// Truncate `text` to at most [`MAX_MESSAGE_LEN`] bytes, respecting the character boundaries.
+fn truncate(text: &str) -> &str {
+    let mut len = text.len().min(MAX_MESSAGE_LEN);
+    while !text.is_char_boundary(len) {
+        len -= 1;
+    }
+    &text[..len]
+}
+
+/// The thread a `Span` was started on.
+///
+/// Tracy requires zones to end on the thread they began on, so this makes `Span` `!Send`, and in
+/// debug builds also remembers the thread so that violations slipping past that (e.g. through
+/// unsafe code) are reported instead of silently corrupting the trace.
+#[cfg(feature="enable")]
+struct SpanThread {
+    #[cfg(debug_assertions)]
+    id: thread::ThreadId,
+    unsend: std::marker::PhantomData<*mut sys::___tracy_c_zone_context>,
+}
+
 #[cfg(feature="enable")]
-pub struct Span(
-    sys::___tracy_c_zone_context,
-    std::marker::PhantomData<*mut sys::___tracy_c_zone_context>,
-);
+impl SpanThread {
+    #[inline]
+    fn current() -> Self {
+        SpanThread {
+            #[cfg(debug_assertions)]
+            id: thread::current().id(),
+            unsend: std::marker::PhantomData,
+        }
+    }
+
+    #[inline]
+    fn check(&self) {
+        #[cfg(debug_assertions)]
+        assert!(
+            self.id == thread::current().id(),
+            "a tracy Span must be dropped on the thread it was created on",
+        );
+    }
+}
 
 #[cfg(not(feature="enable"))]
 pub struct Span(());
@@ -37,43 +134,169 @@ impl Span {
     /// This function allocates the span information on the heap until it is read out by the
     /// profiler.
     ///
+    /// If the `name` is empty, the span will be shown with the name of the `function` instead.
+    ///
     /// `callstack_depth` specifies the maximum number of stack frames client should collect.
-    pub fn new(name: &str, function: &str, file: &str, line: u32, callstack_depth: u16) -> Self {
+    pub fn new(
+        name: &str,
+        function: &str,
+        file: &str,
+        line: u32,
+        callstack_depth: impl Into<StackDepth>,
+    ) -> Self {
         #[cfg(not(feature="enable"))]
         {
             return Self(());
         }
         #[cfg(feature="enable")]
         unsafe {
+            let callstack_depth = callstack_depth.into().frames();
             sys::___tracy_init_thread();
-            let loc = sys::___tracy_alloc_srcloc_name(
-                line,
-                file.as_ptr() as _,
-                file.len(),
-                function.as_ptr() as _,
-                function.len(),
-                name.as_ptr() as _,
-                name.len(),
-            );
+            let loc = if name.is_empty() {
+                sys::___tracy_alloc_srcloc(
+                    line,
+                    file.as_ptr() as _,
+                    file.len(),
+                    function.as_ptr() as _,
+                    function.len(),
+                )
+            } else {
+                sys::___tracy_alloc_srcloc_name(
+                    line,
+                    file.as_ptr() as _,
+                    file.len(),
+                    function.as_ptr() as _,
+                    function.len(),
+                    name.as_ptr() as _,
+                    name.len(),
+                )
+            };
             if callstack_depth == 0 {
                 Self(
                     sys::___tracy_emit_zone_begin_alloc(loc, 1),
-                    std::marker::PhantomData,
+                    SpanThread::current(),
                 )
             } else {
                 Self(
                     sys::___tracy_emit_zone_begin_alloc_callstack(
                         loc,
-                        adjust_stack_depth(callstack_depth).into(),
+                        callstack_depth.into(),
                         1,
                     ),
-                    std::marker::PhantomData,
+                    SpanThread::current(),
                 )
             }
         }
     }
 
+    /// Start a new Tracy span with its color, text and value set right away.
+    ///
+    /// This is equivalent to calling [`Span::new`] followed by [`Span::emit_color`],
+    /// [`Span::emit_text`] and [`Span::emit_value`] for each of the annotations that are `Some`,
+    /// but is more convenient for richly annotated zones. The annotations that are `None` cost
+    /// nothing.
+    ///
+    /// See [`Color`](Color) for the ways to specify the colour.
+    #[allow(clippy::too_many_arguments)]
+    #[inline]
+    pub fn new_annotated(
+        name: &str,
+        function: &str,
+        file: &str,
+        line: u32,
+        callstack_depth: impl Into<StackDepth>,
+        color: Option<Color>,
+        text: Option<&str>,
+        value: Option<u64>,
+    ) -> Self {
+        let span = Self::new(name, function, file, line, callstack_depth);
+        for annotation in Annotation::all(color, text, value) {
+            annotation.emit(&span);
+        }
+        span
+    }
+
+    /// Run `f` within a new Tracy span, returning its result.
+    ///
+    /// The span starts right before `f` is called and ends right after it returns or unwinds.
+    /// The arguments other than `f` are the same as for [`Span::new`]. See also the
+    /// [`scope!`](scope) macro, which fills in the location.
+    pub fn scope<R>(
+        name: &str,
+        function: &str,
+        file: &str,
+        line: u32,
+        callstack_depth: impl Into<StackDepth>,
+        f: impl FnOnce() -> R,
+    ) -> R {
+        let _span = Self::new(name, function, file, line, callstack_depth);
+        f()
+    }
+
+    /// Start a new Tracy span with a static source location and a name known only at runtime.
+    ///
+    /// Unlike [`Span::new`], this does not allocate the source location, which makes it cheaper
+    /// for names that come from elsewhere, e.g. through FFI. Use the
+    /// [`span_cstr!`](span_cstr) macro to create the source location.
+    ///
+    /// `callstack_depth` specifies the maximum number of stack frames client should collect.
+    pub fn new_cstr(
+        location: &'static SourceLocation,
+        name: &CStr,
+        callstack_depth: impl Into<StackDepth>,
+    ) -> Self {
+        let span = Self::from_location(location, callstack_depth);
+        // SAFE: the only way to construct `Span` is by creating a valid tracy zone context.
+        #[cfg(feature="enable")]
+        unsafe {
+            let name = name.to_bytes();
+            sys::___tracy_emit_zone_name(span.0, name.as_ptr() as _, name.len());
+        }
+        span
+    }
+
+    /// Start a new Tracy span at a source location created ahead of time.
+    ///
+    /// Unlike [`Span::new`], this does not allocate the source location every time, so it is
+    /// cheaper for many spans sharing a location only known at runtime. See
+    /// [`SourceLocation::new_leak`].
+    ///
+    /// `callstack_depth` specifies the maximum number of stack frames client should collect.
+    pub fn from_location(
+        location: &'static SourceLocation,
+        callstack_depth: impl Into<StackDepth>,
+    ) -> Self {
+        #[cfg(not(feature="enable"))]
+        {
+            return Self(());
+        }
+        #[cfg(feature="enable")]
+        unsafe {
+            let callstack_depth = callstack_depth.into().frames();
+            sys::___tracy_init_thread();
+            // SAFE: `SourceLocation` can only be constructed from null-terminated strings and
+            // this one lives forever, as Tracy requires.
+            let context = if callstack_depth == 0 {
+                sys::___tracy_emit_zone_begin(&location.0, 1)
+            } else {
+                sys::___tracy_emit_zone_begin_callstack(&location.0, callstack_depth.into(), 1)
+            };
+            Self(context, SpanThread::current())
+        }
+    }
+
+    /// Emit a zero-duration zone at `location`.
+    ///
+    /// This marks an instantaneous event on the timeline of the current thread, as an alternative
+    /// to a [`message`](message). The zone begins and ends immediately.
+    ///
+    /// `callstack_depth` specifies the maximum number of stack frames client should collect.
+    pub fn instant(location: &'static SourceLocation, callstack_depth: impl Into<StackDepth>) {
+        drop(Self::from_location(location, callstack_depth));
+    }
+
     /// Emit a numeric value associated with this span.
+    #[inline]
     pub fn emit_value(&self, value: u64) {
         // SAFE: the only way to construct `Span` is by creating a valid tracy zone context.
         #[cfg(feature="enable")]
@@ -83,6 +306,7 @@ impl Span {
     }
 
     /// Emit some text associated with this span.
+    #[inline]
     pub fn emit_text(&self, text: &str) {
         // SAFE: the only way to construct `Span` is by creating a valid tracy zone context.
         #[cfg(feature="enable")]
@@ -90,258 +314,1849 @@ impl Span {
             sys::___tracy_emit_zone_text(self.0, text.as_ptr() as _, text.len());
         }
     }
-}
 
-impl Drop for Span {
-    fn drop(&mut self) {
+    /// Set the color of this span.
+    ///
+    /// See [`Color`](Color) for the ways to specify the colour.
+    #[inline]
+    pub fn emit_color(&self, color: impl Into<Color>) {
         // SAFE: the only way to construct `Span` is by creating a valid tracy zone context.
         #[cfg(feature="enable")]
         unsafe {
-            sys::___tracy_emit_zone_end(self.0);
+            sys::___tracy_emit_zone_color(self.0, color.into().0 >> 8);
         }
     }
-}
 
-/// A profiling wrapper around an allocator.
-///
-/// See documentation for [`std::alloc`](std::alloc) for more information about global allocators.
-///
-/// # Examples
-///
-/// In your executable, add:
-///
-/// ```rust
-/// # use tracy_client::*;
-/// #[global_allocator]
-/// static GLOBAL: ProfiledAllocator<std::alloc::System> =
-///     ProfiledAllocator::new(std::alloc::System, 100);
-/// ```
-pub struct ProfiledAllocator<T>(T, u16);
+    /// Set the color of this span with the result of `color` once the span ends.
+    ///
+    /// This allows coloring a span by its outcome, such as red when an error occurred. The
+    /// returned guard dereferences to the span, and ends it when dropped, after calling `color`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use tracy_client::*;
+    /// use std::cell::Cell;
+    /// let failed = Cell::new(false);
+    /// let span = Span::new("request", "main", file!(), line!(), 0)
+    ///     .color_on_drop(|| if failed.get() { 0xFF0000FF } else { 0x00FF00FF });
+    /// span.emit_text("processing");
+    /// failed.set(true);
+    /// ```
+    pub fn color_on_drop<F, C>(self, color: F) -> ColorOnDrop<F, C>
+    where
+        F: FnOnce() -> C,
+        C: Into<Color>,
+    {
+        ColorOnDrop {
+            span: mem::ManuallyDrop::new(self),
+            color: Some(color),
+            result: std::marker::PhantomData,
+        }
+    }
 
-impl<T> ProfiledAllocator<T> {
-    pub const fn new(inner_allocator: T, callstack_depth: u16) -> Self {
-        Self(inner_allocator, adjust_stack_depth(callstack_depth))
+    /// Emit the `Display` representation of `value` as the text associated with this span.
+    ///
+    /// The value is formatted into a buffer that is reused by every call on the same thread, so
+    /// unlike `emit_text(&value.to_string())` this does not usually allocate. Text longer than
+    /// [`MAX_MESSAGE_LEN`] is truncated.
+    pub fn emit_display(&self, value: impl std::fmt::Display) {
+        with_formatted(format_args!("{}", value), |text| self.emit_text(text));
     }
 
-    fn emit_alloc(&self, ptr: *mut u8, size: usize) -> *mut u8 {
+    /// Emit the `Debug` representation of `value` as the text associated with this span.
+    ///
+    /// See [`emit_display`](Span::emit_display).
+    pub fn emit_debug(&self, value: impl std::fmt::Debug) {
+        with_formatted(format_args!("{:?}", value), |text| self.emit_text(text));
+    }
+
+    /// Emit some arbitrary bytes as the text associated with this span.
+    ///
+    /// Tracy does not require the text to be UTF-8, but the viewer will display it as such, so
+    /// non-UTF-8 data may be rendered oddly.
+    #[inline]
+    pub fn emit_bytes(&self, bytes: &[u8]) {
+        // SAFE: the only way to construct `Span` is by creating a valid tracy zone context.
         #[cfg(feature="enable")]
         unsafe {
-            if self.1 == 0 {
-                sys::___tracy_emit_memory_alloc(ptr as _, size, 1);
-            } else {
-                sys::___tracy_emit_memory_alloc_callstack(ptr as _, size, self.1.into(), 1);
-            }
+            sys::___tracy_emit_zone_text(self.0, bytes.as_ptr() as _, bytes.len());
         }
-        ptr
     }
+}
 
-    fn emit_free(&self, ptr: *mut u8) -> *mut u8 {
+impl Drop for Span {
+    #[inline]
+    fn drop(&mut self) {
+        #[cfg(feature="enable")]
+        self.1.check();
+        // SAFE: the only way to construct `Span` is by creating a valid tracy zone context.
         #[cfg(feature="enable")]
         unsafe {
-            if self.1 == 0 {
-                sys::___tracy_emit_memory_free(ptr as _, 1);
-            } else {
-                sys::___tracy_emit_memory_free_callstack(ptr as _, self.1.into(), 1);
-            }
+            sys::___tracy_emit_zone_end(self.0);
         }
-        ptr
     }
 }
 
-unsafe impl<T: alloc::GlobalAlloc> alloc::GlobalAlloc for ProfiledAllocator<T> {
-    unsafe fn alloc(&self, layout: alloc::Layout) -> *mut u8 {
-        self.emit_alloc(self.0.alloc(layout), layout.size())
-    }
-
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: alloc::Layout) {
-        self.0.dealloc(self.emit_free(ptr), layout)
-    }
+/// An annotation emitted by [`Span::new_annotated`] on the span it starts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Annotation<'a> {
+    Color(Color),
+    Text(&'a str),
+    Value(u64),
+}
 
-    unsafe fn alloc_zeroed(&self, layout: alloc::Layout) -> *mut u8 {
-        self.emit_alloc(self.0.alloc_zeroed(layout), layout.size())
+impl<'a> Annotation<'a> {
+    /// The annotations that are `Some`, in the order they are emitted.
+    fn all(
+        color: Option<Color>,
+        text: Option<&'a str>,
+        value: Option<u64>,
+    ) -> impl Iterator<Item = Self> {
+        let color = color.map(Annotation::Color);
+        let text = text.map(Annotation::Text);
+        let value = value.map(Annotation::Value);
+        color.into_iter().chain(text).chain(value)
     }
 
-    unsafe fn realloc(&self, ptr: *mut u8, layout: alloc::Layout, new_size: usize) -> *mut u8 {
-        self.emit_alloc(
-            self.0.realloc(self.emit_free(ptr), layout, new_size),
-            new_size,
-        )
+    fn emit(self, span: &Span) {
+        match self {
+            Annotation::Color(color) => span.emit_color(color),
+            Annotation::Text(text) => span.emit_text(text),
+            Annotation::Value(value) => span.emit_value(value),
+        }
     }
 }
 
-/// Indicate that rendering of a continuous frame has ended.
+/// A span colored once it ends.
 ///
-/// Typically should be inserted after a buffer swap.
-///
-/// In case you want to annotate secondary continuous frame sets, call the macro with a string
-/// argument.
-///
-/// For non-continuous frame sets see [`Frame`](Frame).
-///
-/// # Examples
-///
-/// ```no_run
-/// # use tracy_client::*;
-/// # fn swap_buffers() {}
-/// swap_buffers();
-/// finish_continuous_frame!();
-/// finish_continuous_frame!("some other frame loop");
-/// ```
-#[macro_export]
-macro_rules! finish_continuous_frame {
-    () => {
-        unsafe {
-            $crate::finish_continuous_frame(std::ptr::null());
-        }
-    };
-    ($name: literal) => {
-        unsafe {
-            $crate::finish_continuous_frame(concat!($name, "\0").as_ptr() as _);
-        }
-    };
+/// Created with [`Span::color_on_drop`](Span::color_on_drop).
+pub struct ColorOnDrop<F: FnOnce() -> C, C: Into<Color>> {
+    span: mem::ManuallyDrop<Span>,
+    color: Option<F>,
+    result: std::marker::PhantomData<fn() -> C>,
 }
 
-/// Use `finish_continuous_frame!` instead.
-///
-/// `name` must contain a NULL byte.
-#[doc(hidden)]
-pub unsafe fn finish_continuous_frame(name: *const u8) {
-    #[cfg(feature="enable")]
-    {
-        sys::___tracy_emit_frame_mark(name as _);
+impl<F: FnOnce() -> C, C: Into<Color>> std::ops::Deref for ColorOnDrop<F, C> {
+    type Target = Span;
+
+    fn deref(&self) -> &Span {
+        &self.span
     }
 }
 
-/// Start a non-continuous frame region.
-#[macro_export]
-macro_rules! start_noncontinuous_frame {
-    ($name: literal) => {
-        unsafe {
-            $crate::Frame::start_noncontinuous_frame(concat!($name, "\0"))
+impl<F: FnOnce() -> C, C: Into<Color>> Drop for ColorOnDrop<F, C> {
+    fn drop(&mut self) {
+        // The span is only ended after the closure has run, even if it panics.
+        struct End<'a>(&'a mut mem::ManuallyDrop<Span>);
+        impl Drop for End<'_> {
+            fn drop(&mut self) {
+                // SAFE: the span is dropped exactly once, here.
+                unsafe { mem::ManuallyDrop::drop(self.0) }
+            }
         }
-    };
+        let end = End(&mut self.span);
+        if let Some(color) = self.color.take() {
+            end.0.emit_color(color());
+        }
+    }
 }
 
-/// A non-continuous frame region.
+/// A source location that can be shared by any number of spans.
 ///
-/// Create with the [`start_noncontinuous_frame`](start_noncontinuous_frame) macro.
-pub struct Frame(&'static str);
+/// Created by the [`span_cstr!`](span_cstr) macro or with
+/// [`SourceLocation::new_leak`](SourceLocation::new_leak).
+#[cfg(feature="enable")]
+pub struct SourceLocation(sys::___tracy_source_location_data);
 
-impl Frame {
-    /// Use `start_noncontinuous_frame!` instead.
+#[cfg(not(feature="enable"))]
+pub struct SourceLocation(());
+
+// SAFE: the source location only points to immutable `'static` strings.
+#[cfg(feature="enable")]
+unsafe impl Sync for SourceLocation {}
+
+impl SourceLocation {
+    /// Use `span_cstr!` instead.
     ///
-    /// `name` must contain a NULL byte.
+    /// `function` and `file` must be null-terminated.
     #[doc(hidden)]
-    pub unsafe fn start_noncontinuous_frame(name: &'static str) -> Frame {
+    pub const unsafe fn new_unchecked(
+        function: &'static str,
+        file: &'static str,
+        line: u32,
+    ) -> Self {
         #[cfg(feature="enable")]
         {
-            sys::___tracy_emit_frame_mark_start(name.as_ptr() as _);
+            Self(sys::___tracy_source_location_data {
+                name: std::ptr::null(),
+                function: function.as_ptr() as _,
+                file: file.as_ptr() as _,
+                line,
+                color: 0,
+            })
+        }
+        #[cfg(not(feature="enable"))]
+        {
+            Self(())
         }
-        Self(name)
     }
-}
 
-impl Drop for Frame {
-    fn drop(&mut self) {
+    /// Create a source location from data only known at runtime.
+    ///
+    /// The allocation made by [`Span::new`] for the source location is consumed by Tracy and
+    /// cannot be reused. This location, on the other hand, is leaked, and any number of spans
+    /// can then be started with [`Span::from_location`] without allocating it again. As the
+    /// location is never freed, this should only be used with a small, fixed set of locations.
+    ///
+    /// If the `name` is empty, the spans will be shown with the name of the `function` instead.
+    ///
+    /// # Panics
+    ///
+    /// If any of the strings contains a null byte.
+    pub fn new_leak(name: &str, function: &str, file: &str, line: u32) -> &'static Self {
         #[cfg(feature="enable")]
-        unsafe {
-            sys::___tracy_emit_frame_mark_end(self.0.as_ptr() as _);
+        {
+            fn leak(s: &str) -> *const std::os::raw::c_char {
+                CString::new(s).expect("source location contains a null byte").into_raw()
+            }
+            Box::leak(Box::new(Self(sys::___tracy_source_location_data {
+                name: if name.is_empty() { std::ptr::null() } else { leak(name) },
+                function: leak(function),
+                file: leak(file),
+                line,
+                color: 0,
+            })))
+        }
+        #[cfg(not(feature="enable"))]
+        {
+            &SourceLocation(())
         }
     }
 }
 
-/// Output a message.
-///
-/// `callstack_depth` specifies the maximum number of stack frames client should collect.
-pub fn message(message: &str, callstack_depth: u16) {
-    #[cfg(feature="enable")]
-    unsafe {
-        sys::___tracy_init_thread();
-        sys::___tracy_emit_message(
-            message.as_ptr() as _,
-            message.len(),
-            adjust_stack_depth(callstack_depth).into(),
-        )
-    }
-}
-
-/// Output a message with an associated color.
+/// A cache of source locations created from data only known at runtime.
 ///
-/// `callstack_depth` specifies the maximum number of stack frames client should collect.
+/// Each distinct location is [leaked](SourceLocation::new_leak) the first time it is asked for,
+/// and the same location is returned for it from then on. This lets code without a static call
+/// site, such as plugins, start spans with the allocation-free [`Span::from_location`] for
+/// locations that repeat.
 ///
-/// The colour shall be provided as RGBA, where the least significant 8 bits represent the alpha
-/// component and most significant 8 bits represent the red component.
-pub fn color_message(message: &str, rgba: u32, callstack_depth: u16) {
-    #[cfg(feature="enable")]
-    unsafe {
-        sys::___tracy_init_thread();
-        sys::___tracy_emit_messageC(
-            message.as_ptr() as _,
-            message.len(),
-            rgba >> 8,
-            adjust_stack_depth(callstack_depth).into(),
-        )
-    }
-}
-
-/// Set the current thread name to the provided value.
-pub fn set_thread_name(name: &str) {
-    #[cfg(feature="enable")]
-    unsafe {
-        let name = CString::new(name).unwrap();
-        // SAFE: `name` is a valid null-terminated string.
-        sys::___tracy_set_thread_name(name.as_ptr() as _)
-    }
-}
-
-/// Create an instance of plot that can plot arbitrary `f64` values.
+/// Looking a location up takes a lock shared by all the users of the pool, so the returned
+/// location should be kept, e.g. alongside the plugin it belongs to, rather than looked up again
+/// for every span.
 ///
 /// # Examples
 ///
 /// ```
-/// # use tracy_client::*;
-/// static TEMPERATURE: Plot = create_plot!("temperature");
-/// TEMPERATURE.point(37.0);
+/// use tracy_client::{Span, SourceLocationPool};
+/// static LOCATIONS: SourceLocationPool = SourceLocationPool::new();
+/// // Look the locations up once, when the plugins are loaded...
+/// let plugins: Vec<_> = ["first", "second", "first"]
+///     .iter()
+///     .map(|&plugin| LOCATIONS.get(plugin, "run_plugin", "plugins.rs", 1))
+///     .collect();
+/// // ...and start spans with them every time the plugins run.
+/// for _ in 0..10 {
+///     for &location in &plugins {
+///         let _span = Span::from_location(location, 0);
+///     }
+/// }
 /// ```
-#[macro_export]
-macro_rules! create_plot {
-    ($name: literal) => {
-        unsafe { $crate::Plot::new_unchecked(concat!($name, "\0")) }
-    };
+pub struct SourceLocationPool {
+    /// The locations created so far, keyed by the hash of their name, function, file and line.
+    locations: Lazy<Mutex<HashMap<u64, Vec<PooledLocation>>>>,
 }
 
-/// A plot for plotting arbitary `f64` values.
-///
-/// Create with the [`create_plot`](create_plot) macro.
-pub struct Plot(&'static str);
+struct PooledLocation {
+    name: String,
+    function: String,
+    file: String,
+    line: u32,
+    location: &'static SourceLocation,
+}
+
+impl SourceLocationPool {
+    /// Create an empty pool.
+    pub const fn new() -> Self {
+        Self { locations: Lazy::new() }
+    }
+
+    fn locations(&self) -> std::sync::MutexGuard<'_, HashMap<u64, Vec<PooledLocation>>> {
+        let locations = self.locations.get_or_init(Default::default);
+        locations.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Get the location with this `name`, `function`, `file` and `line`, creating it if this is
+    /// the first time it is asked for.
+    ///
+    /// As the locations are never freed, this should only be used with a small, fixed set of
+    /// locations. This locks the pool, so it should not be called for every span: keep the
+    /// returned location instead, it can be used to start any number of spans.
+    ///
+    /// # Panics
+    ///
+    /// If any of the strings contains a null byte.
+    pub fn get(
+        &self,
+        name: &str,
+        function: &str,
+        file: &str,
+        line: u32,
+    ) -> &'static SourceLocation {
+        let mut hasher = DefaultHasher::new();
+        (name, function, file, line).hash(&mut hasher);
+        let mut locations = self.locations();
+        let bucket = locations.entry(hasher.finish()).or_default();
+        let pooled = bucket.iter().find(|pooled| {
+            pooled.name == name
+                && pooled.function == function
+                && pooled.file == file
+                && pooled.line == line
+        });
+        if let Some(pooled) = pooled {
+            return pooled.location;
+        }
+        let location = SourceLocation::new_leak(name, function, file, line);
+        bucket.push(PooledLocation {
+            name: name.to_owned(),
+            function: function.to_owned(),
+            file: file.to_owned(),
+            line,
+            location,
+        });
+        location
+    }
+
+    /// The number of distinct locations created by this pool.
+    pub fn len(&self) -> usize {
+        self.locations().values().map(Vec::len).sum()
+    }
+
+    /// Whether no location has been created by this pool yet.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for SourceLocationPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Start a span named by a `&CStr`, with a static source location.
+///
+/// The function of the source location is the path of the module the macro is used in.
+///
+/// # Examples
+///
+/// ```
+/// # use tracy_client::*;
+/// # let name = std::ffi::CString::new("from C").unwrap();
+/// let name: &std::ffi::CStr = &name;
+/// let _span = span_cstr!(name, 0);
+/// ```
+#[macro_export]
+macro_rules! span_cstr {
+    ($name: expr, $callstack_depth: expr) => {{
+        const LINE: u32 = line!();
+        // SAFE: both strings are null-terminated.
+        static LOCATION: $crate::SourceLocation = unsafe {
+            $crate::SourceLocation::new_unchecked(
+                concat!(module_path!(), "\0"),
+                concat!(file!(), "\0"),
+                LINE,
+            )
+        };
+        $crate::Span::new_cstr(&LOCATION, $name, $callstack_depth)
+    }};
+}
+
+/// Run a closure within a new span, returning its result.
+///
+/// The function of the span's source location is the path of the module the macro is used in.
+/// Without a callstack depth, the [default depth](set_default_stack_depth) is used.
+///
+/// # Examples
+///
+/// ```
+/// # use tracy_client::*;
+/// let sum = scope!("sum", || (1..=10).sum::<u32>());
+/// let product = scope!("product", 10, || (1..=10).product::<u32>());
+/// # assert_eq!((sum, product), (55, 3628800));
+/// ```
+#[macro_export]
+macro_rules! scope {
+    ($name: expr, $f: expr) => {
+        $crate::scope!($name, $crate::StackDepth::Default, $f)
+    };
+    ($name: expr, $callstack_depth: expr, $f: expr) => {
+        $crate::Span::scope($name, module_path!(), file!(), line!(), $callstack_depth, $f)
+    };
+}
+
+/// Start a span covering the rest of the enclosing function.
+///
+/// The span is named after the function, and lasts until the end of the block the macro is used
+/// in, so the macro should be the first statement of the function's body. Without a callstack
+/// depth, the [default depth](set_default_stack_depth) is used.
+///
+/// # Examples
+///
+/// ```
+/// # use tracy_client::*;
+/// fn update_physics() {
+///     span_fn!();
+///     // ...
+/// }
+///
+/// fn render() {
+///     span_fn!(10);
+///     // ...
+/// }
+/// # update_physics();
+/// # render();
+/// ```
+#[macro_export]
+macro_rules! span_fn {
+    () => {
+        $crate::span_fn!($crate::StackDepth::Default)
+    };
+    ($callstack_depth: expr) => {
+        let _span = {
+            fn f() {}
+            fn type_name_of<T>(_: T) -> &'static str {
+                std::any::type_name::<T>()
+            }
+            let function = $crate::function_name(type_name_of(f));
+            $crate::Span::new("", function, file!(), line!(), $callstack_depth)
+        };
+    };
+}
+
+/// Use `span_fn!` instead.
+///
+/// Extracts the path of the enclosing function from the type name of a function `f` defined in it.
+#[doc(hidden)]
+pub fn function_name(type_name: &'static str) -> &'static str {
+    const CLOSURE: &str = "::{{closure}}";
+    let mut name = type_name;
+    if name.ends_with("::f") {
+        name = &name[..name.len() - "::f".len()];
+    }
+    while name.ends_with(CLOSURE) {
+        name = &name[..name.len() - CLOSURE.len()];
+    }
+    name
+}
+
+/// A token identifying a zone started with [`begin_discontinuous`](begin_discontinuous).
+///
+/// Unlike a [`Span`](Span), a token is a plain value that can be copied and stored anywhere, e.g.
+/// in a map keyed by an I/O request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ZoneToken(usize);
+
+thread_local! {
+    /// Zones started with `begin_discontinuous` that have not been ended yet.
+    static DISCONTINUOUS_ZONES: RefCell<HashMap<usize, Span>> = RefCell::new(HashMap::new());
+}
+
+/// Shared by all threads, so that a token never refers to a zone of another thread.
+static NEXT_ZONE_TOKEN: AtomicUsize = AtomicUsize::new(0);
+
+/// Start a zone that is not bound to a lexical scope.
+///
+/// The zone lasts until the returned token is passed to
+/// [`end_discontinuous`](end_discontinuous). This is useful for operations that begin and end in
+/// different callbacks, where holding on to a [`Span`](Span) is not practical.
+///
+/// The arguments have the same meaning as for [`Span::new`](Span::new).
+///
+/// Tracy requires zones to be ended on the thread they were started on, and in the reverse order
+/// of starting them. Tokens are only valid on the thread they were created on.
+pub fn begin_discontinuous(
+    name: &str,
+    function: &str,
+    file: &str,
+    line: u32,
+    callstack_depth: impl Into<StackDepth>,
+) -> ZoneToken {
+    let span = Span::new(name, function, file, line, callstack_depth);
+    let token = NEXT_ZONE_TOKEN.fetch_add(1, Ordering::Relaxed);
+    DISCONTINUOUS_ZONES.with(|zones| zones.borrow_mut().insert(token, span));
+    ZoneToken(token)
+}
+
+/// End a zone started with [`begin_discontinuous`](begin_discontinuous).
+///
+/// Returns `false` if the token does not refer to a zone started on this thread that is still
+/// running.
+pub fn end_discontinuous(token: ZoneToken) -> bool {
+    let span = DISCONTINUOUS_ZONES.with(|zones| zones.borrow_mut().remove(&token.0));
+    span.is_some()
+}
+
+/// Instrument a future with Tracy zones.
+///
+/// A zone is entered every time the future is polled and is exited once the poll returns, so the
+/// time spent waiting for the future to be woken up is not attributed to the zone. The zone
+/// emitted for the final poll carries the total time spent polling the future (in nanoseconds)
+/// as its value and the number of polls as its text.
+///
+/// The location and `callstack_depth` arguments have the same meaning as for
+/// [`Span::new`](Span::new).
+///
+/// # Examples
+///
+/// ```
+/// # use tracy_client::*;
+/// # async fn fetch() {}
+/// let future = instrument_future("fetch", "main", file!(), line!(), 0, fetch());
+/// ```
+pub fn instrument_future<F: Future>(
+    name: &'static str,
+    function: &'static str,
+    file: &'static str,
+    line: u32,
+    callstack_depth: impl Into<StackDepth>,
+    future: F,
+) -> InstrumentedFuture<F> {
+    InstrumentedFuture {
+        future,
+        name,
+        function,
+        file,
+        line,
+        callstack_depth: callstack_depth.into(),
+        polls: 0,
+        busy: Duration::from_secs(0),
+    }
+}
+
+/// A future instrumented with Tracy zones.
+///
+/// Create with the [`instrument_future`](instrument_future) function.
+pub struct InstrumentedFuture<F> {
+    future: F,
+    name: &'static str,
+    function: &'static str,
+    file: &'static str,
+    line: u32,
+    callstack_depth: StackDepth,
+    polls: u64,
+    busy: Duration,
+}
+
+impl<F: Future> Future for InstrumentedFuture<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        // SAFE: `future` is structurally pinned and is never moved out of `self`.
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        // A new zone is created for every poll, as `Span` must not leave the thread it has been
+        // created on, while the future may be polled from different threads.
+        let span = Span::new(this.name, this.function, this.file, this.line, this.callstack_depth);
+        let start = Instant::now();
+        let result = future.poll(cx);
+        this.busy += start.elapsed();
+        this.polls += 1;
+        if result.is_ready() {
+            span.emit_value(this.busy.as_nanos() as u64);
+            span.emit_text(&format!("{} polls", this.polls));
+        }
+        result
+    }
+}
+
+/// A span whose text accumulates over all the times it is entered.
+///
+/// Every [`enter`](AccumulatingSpan::enter) starts a new zone at the same location, so an
+/// `AccumulatingSpan` can be kept across the polls of a future, or anywhere else a [`Span`] would
+/// have to leave its thread. Each new zone carries all the text
+/// [appended](AccumulatingSpan::append) so far, one line per call, so the context recorded by the
+/// earlier zones is not lost on the later ones. Text beyond [`MAX_MESSAGE_LEN`] bytes is not
+/// shown.
+///
+/// # Examples
+///
+/// ```
+/// # use tracy_client::*;
+/// let location = SourceLocation::new_leak("request", "handle", file!(), line!());
+/// let mut request = AccumulatingSpan::new(location, 0);
+/// request.append("accepted");
+/// drop(request.enter());
+/// request.append("headers parsed");
+/// // The zone shows both lines.
+/// let _zone = request.enter();
+/// ```
+pub struct AccumulatingSpan {
+    location: &'static SourceLocation,
+    callstack_depth: StackDepth,
+    text: String,
+}
+
+impl AccumulatingSpan {
+    /// Create a span whose zones start at `location`, collecting at most `callstack_depth` stack
+    /// frames each.
+    pub fn new(location: &'static SourceLocation, callstack_depth: impl Into<StackDepth>) -> Self {
+        Self { location, callstack_depth: callstack_depth.into(), text: String::new() }
+    }
+
+    /// Append a line of text, shown on the zones started by the following `enter`s.
+    pub fn append(&mut self, text: &str) {
+        if !self.text.is_empty() {
+            self.text.push('\n');
+        }
+        self.text.push_str(text);
+    }
+
+    /// The text accumulated so far.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Start a new zone carrying all the text accumulated so far.
+    pub fn enter(&self) -> Span {
+        let span = Span::from_location(self.location, self.callstack_depth);
+        if !self.text.is_empty() {
+            span.emit_text(truncate(&self.text));
+        }
+        span
+    }
+}
+
+/// A profiling wrapper around an allocator.
+///
+/// See documentation for [`std::alloc`](std::alloc) for more information about global allocators.
+///
+/// # Examples
+///
+/// In your executable, add:
+///
+/// ```rust
+/// # use tracy_client::*;
+/// #[global_allocator]
+/// static GLOBAL: ProfiledAllocator<std::alloc::System> =
+///     ProfiledAllocator::new(std::alloc::System, 100);
+/// ```
+///
+/// # Secure memory events
+///
+/// By default the memory events are emitted in Tracy's "secure" mode, in which Tracy verifies
+/// that the profiler is available before recording each event. This makes the allocator safe to
+/// use at any point of the program's lifetime, including before the profiler is initialized and
+/// after it has been destroyed, which is necessary for a global allocator.
+///
+/// An allocator constructed with [`new_insecure`](ProfiledAllocator::new_insecure) skips this
+/// check. It is slightly faster, but must only be used while the profiler is running.
+///
+/// Tracy can only match a free to its allocation if both were emitted in the same mode. This
+/// holds by construction: the mode is fixed for the lifetime of an allocator, and memory has to be
+/// released by the same allocator that allocated it.
+///
+/// Allocators with more options can be configured with a
+/// [`builder`](ProfiledAllocator::builder).
+#[cfg_attr(not(feature="enable"), allow(dead_code))]
+pub struct ProfiledAllocator<T> {
+    inner: T,
+    callstack_depth: StackDepth,
+    secure: bool,
+    min_size: usize,
+}
+
+impl<T> ProfiledAllocator<T> {
+    pub const fn new(inner_allocator: T, callstack_depth: u16) -> Self {
+        Self::builder(inner_allocator).callstack_depth(callstack_depth).build()
+    }
+
+    /// Same as [`new`](ProfiledAllocator::new), but with the depth specified as a
+    /// [`StackDepth`](StackDepth).
+    pub const fn with_stack_depth(inner_allocator: T, callstack_depth: StackDepth) -> Self {
+        Self::builder(inner_allocator).stack_depth(callstack_depth).build()
+    }
+
+    /// Same as [`new`](ProfiledAllocator::new), but emits insecure memory events.
+    ///
+    /// # Safety
+    ///
+    /// The allocator must only be used while the profiler is running. In particular it must not
+    /// be used as the global allocator.
+    pub const unsafe fn new_insecure(inner_allocator: T, callstack_depth: u16) -> Self {
+        Self::builder(inner_allocator).callstack_depth(callstack_depth).insecure().build()
+    }
+
+    /// Start configuring an allocator wrapping `inner_allocator`.
+    ///
+    /// All the methods of the builder are `const`, so the allocator can still be constructed in
+    /// a `static`:
+    ///
+    /// ```rust
+    /// # use tracy_client::*;
+    /// #[global_allocator]
+    /// static GLOBAL: ProfiledAllocator<std::alloc::System> =
+    ///     ProfiledAllocator::builder(std::alloc::System)
+    ///         .callstack_depth(16)
+    ///         .min_size(1024)
+    ///         .build();
+    /// ```
+    pub const fn builder(inner_allocator: T) -> ProfiledAllocatorBuilder<T> {
+        ProfiledAllocatorBuilder {
+            inner: mem::ManuallyDrop::new(inner_allocator),
+            callstack_depth: StackDepth::Frames(0),
+            secure: true,
+            min_size: 0,
+        }
+    }
+
+    fn emit_alloc(&self, ptr: *mut u8, size: usize) -> *mut u8 {
+        #[cfg(feature="enable")]
+        unsafe {
+            if size < self.min_size {
+                return ptr;
+            }
+            let depth = self.callstack_depth.frames();
+            let secure = self.secure.into();
+            if depth == 0 {
+                sys::___tracy_emit_memory_alloc(ptr as _, size, secure);
+            } else {
+                sys::___tracy_emit_memory_alloc_callstack(ptr as _, size, depth.into(), secure);
+            }
+        }
+        ptr
+    }
+
+    fn emit_free(&self, ptr: *mut u8, size: usize) -> *mut u8 {
+        #[cfg(feature="enable")]
+        unsafe {
+            if size < self.min_size {
+                return ptr;
+            }
+            let depth = self.callstack_depth.frames();
+            let secure = self.secure.into();
+            if depth == 0 {
+                sys::___tracy_emit_memory_free(ptr as _, secure);
+            } else {
+                sys::___tracy_emit_memory_free_callstack(ptr as _, depth.into(), secure);
+            }
+        }
+        ptr
+    }
+}
+
+/// A builder for a [`ProfiledAllocator`](ProfiledAllocator).
+///
+/// Created with [`ProfiledAllocator::builder`](ProfiledAllocator::builder). By default the
+/// allocator collects no callstacks, emits secure memory events and reports allocations of all
+/// sizes.
+pub struct ProfiledAllocatorBuilder<T> {
+    // Without a destructor, the builder methods can take `self` by value in a `const fn`.
+    inner: mem::ManuallyDrop<T>,
+    callstack_depth: StackDepth,
+    secure: bool,
+    min_size: usize,
+}
+
+impl<T> ProfiledAllocatorBuilder<T> {
+    /// Collect at most `callstack_depth` stack frames for every memory event.
+    pub const fn callstack_depth(self, callstack_depth: u16) -> Self {
+        self.stack_depth(StackDepth::Frames(adjust_stack_depth(callstack_depth)))
+    }
+
+    /// Same as [`callstack_depth`](ProfiledAllocatorBuilder::callstack_depth), but with the depth
+    /// specified as a [`StackDepth`](StackDepth).
+    pub const fn stack_depth(self, callstack_depth: StackDepth) -> Self {
+        Self { callstack_depth, ..self }
+    }
+
+    /// Only report allocations of at least `min_size` bytes.
+    ///
+    /// The smaller allocations are not shown in Tracy at all. This reduces the profiling
+    /// overhead of programs making many small allocations.
+    pub const fn min_size(self, min_size: usize) -> Self {
+        Self { min_size, ..self }
+    }
+
+    /// Emit insecure memory events.
+    ///
+    /// # Safety
+    ///
+    /// The same as for [`ProfiledAllocator::new_insecure`](ProfiledAllocator::new_insecure).
+    pub const unsafe fn insecure(self) -> Self {
+        Self { secure: false, ..self }
+    }
+
+    /// Finish configuring the allocator.
+    pub const fn build(self) -> ProfiledAllocator<T> {
+        ProfiledAllocator {
+            inner: mem::ManuallyDrop::into_inner(self.inner),
+            callstack_depth: self.callstack_depth,
+            secure: self.secure,
+            min_size: self.min_size,
+        }
+    }
+}
+
+unsafe impl<T: alloc::GlobalAlloc> alloc::GlobalAlloc for ProfiledAllocator<T> {
+    unsafe fn alloc(&self, layout: alloc::Layout) -> *mut u8 {
+        self.emit_alloc(self.inner.alloc(layout), layout.size())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: alloc::Layout) {
+        self.inner.dealloc(self.emit_free(ptr, layout.size()), layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: alloc::Layout) -> *mut u8 {
+        self.emit_alloc(self.inner.alloc_zeroed(layout), layout.size())
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: alloc::Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = self.inner.realloc(ptr, layout, new_size);
+        if reallocation_changed(ptr, layout.size(), new_ptr, new_size) {
+            // Tracy has no event for resizing an allocation, so the change is reported as a free
+            // of the old memory followed by an allocation of the new one. If the memory moved,
+            // another thread may have been given the old address in the meantime and reported
+            // its allocation before this free.
+            self.emit_free(ptr, layout.size());
+            self.emit_alloc(new_ptr, new_size);
+        }
+        new_ptr
+    }
+}
+
+/// Whether a reallocation has to be reported to Tracy. A failed reallocation leaves the old
+/// memory as it was, and so does one done in place that keeps the size.
+fn reallocation_changed(ptr: *mut u8, size: usize, new_ptr: *mut u8, new_size: usize) -> bool {
+    !new_ptr.is_null() && (new_ptr != ptr || new_size != size)
+}
+
+/// Indicate that rendering of a continuous frame has ended.
+///
+/// Typically should be inserted after a buffer swap.
+///
+/// In case you want to annotate secondary continuous frame sets, call the macro with a string
+/// argument.
+///
+/// For non-continuous frame sets see [`Frame`](Frame).
+///
+/// # Examples
+///
+/// ```no_run
+/// # use tracy_client::*;
+/// # fn swap_buffers() {}
+/// swap_buffers();
+/// finish_continuous_frame!();
+/// finish_continuous_frame!("some other frame loop");
+/// ```
+#[macro_export]
+macro_rules! finish_continuous_frame {
+    () => {
+        unsafe {
+            $crate::finish_continuous_frame(std::ptr::null());
+        }
+    };
+    ($name: literal) => {
+        unsafe {
+            $crate::finish_continuous_frame(concat!($name, "\0").as_ptr() as _);
+        }
+    };
+}
+
+/// Use `finish_continuous_frame!` instead.
+///
+/// `name` must contain a NULL byte.
+#[doc(hidden)]
+#[inline]
+pub unsafe fn finish_continuous_frame(name: *const u8) {
+    #[cfg(debug_assertions)]
+    {
+        if name.is_null() {
+            FRAME_MARKED.store(true, Ordering::Relaxed);
+        }
+    }
+    #[cfg(feature="enable")]
+    {
+        sys::___tracy_emit_frame_mark(name as _);
+    }
+}
+
+/// Whether the end of any frame of the main frame set has been marked.
+///
+/// Only tracked in debug builds, to warn about [frame images](frame_image) with no frame to
+/// attach to.
+#[cfg(debug_assertions)]
+static FRAME_MARKED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+/// Start a non-continuous frame region.
+#[macro_export]
+macro_rules! start_noncontinuous_frame {
+    ($name: literal) => {
+        unsafe {
+            $crate::Frame::start_noncontinuous_frame(concat!($name, "\0"))
+        }
+    };
+}
+
+/// A non-continuous frame region.
+///
+/// Create with the [`start_noncontinuous_frame`](start_noncontinuous_frame) macro.
+#[cfg_attr(not(feature="enable"), allow(dead_code))]
+pub struct Frame(&'static str);
+
+impl Frame {
+    /// Use `start_noncontinuous_frame!` instead.
+    ///
+    /// `name` must contain a NULL byte.
+    #[doc(hidden)]
+    #[inline]
+    pub unsafe fn start_noncontinuous_frame(name: &'static str) -> Frame {
+        #[cfg(feature="enable")]
+        {
+            sys::___tracy_emit_frame_mark_start(name.as_ptr() as _);
+        }
+        Self(name)
+    }
+}
+
+impl Drop for Frame {
+    #[inline]
+    fn drop(&mut self) {
+        #[cfg(feature="enable")]
+        unsafe {
+            sys::___tracy_emit_frame_mark_end(self.0.as_ptr() as _);
+        }
+    }
+}
+
+/// A zone covering one iteration of the main frame loop.
+///
+/// Dropping the guard ends the zone and then marks the end of the frame, as with
+/// [`finish_continuous_frame!()`](finish_continuous_frame), so a loop body needs just one guard
+/// to show up both as a zone and as a frame:
+///
+/// ```
+/// use tracy_client::{FrameIteration, SourceLocation};
+/// let location = SourceLocation::new_leak("render loop", "main", file!(), line!());
+/// for _ in 0..3 {
+///     let _iteration = FrameIteration::new(location, 0);
+///     // render the frame...
+/// }
+/// ```
+pub struct FrameIteration(Option<Span>);
+
+impl FrameIteration {
+    /// Start a zone at `location` for this iteration, collecting at most `callstack_depth` stack
+    /// frames.
+    pub fn new(location: &'static SourceLocation, callstack_depth: impl Into<StackDepth>) -> Self {
+        Self(Some(Span::from_location(location, callstack_depth)))
+    }
+}
+
+impl std::ops::Deref for FrameIteration {
+    type Target = Span;
+    fn deref(&self) -> &Span {
+        self.0.as_ref().expect("the zone is only taken on drop")
+    }
+}
+
+impl Drop for FrameIteration {
+    fn drop(&mut self) {
+        drop(self.0.take());
+        // SAFE: a null name marks the end of a frame of the main frame set.
+        unsafe {
+            finish_continuous_frame(std::ptr::null());
+        }
+    }
+}
+
+/// Where the first row of a [`frame_image`](frame_image) is displayed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageOrigin {
+    /// The first row is the top of the image, as in Direct3D, Vulkan and most image formats.
+    TopLeft,
+    /// The first row is the bottom of the image, as with `glReadPixels` in OpenGL.
+    ///
+    /// Tracy flips these images vertically when displaying them.
+    BottomLeft,
+}
+
+impl ImageOrigin {
+    /// The origin of an image that Tracy is asked to flip vertically if `flip` is set.
+    fn from_flip(flip: bool) -> Self {
+        if flip {
+            ImageOrigin::BottomLeft
+        } else {
+            ImageOrigin::TopLeft
+        }
+    }
+
+    /// Whether Tracy needs to flip the image vertically.
+    #[cfg_attr(not(feature="enable"), allow(dead_code))]
+    fn flip(self) -> bool {
+        match self {
+            ImageOrigin::TopLeft => false,
+            ImageOrigin::BottomLeft => true,
+        }
+    }
+}
+
+/// Attach a screenshot to a frame.
+///
+/// `image` must contain `width * height` RGBA pixels, with 8 bits per component, and both
+/// dimensions must be divisible by 4. Tracy copies the image, so the buffer can be reused right
+/// away. The image is attached to the frame `offset` frames before the current one, which allows
+/// for the latency of reading the image back from the GPU.
+///
+/// In debug builds, a warning message is emitted for the first image emitted before the end of
+/// any frame was marked with [`finish_continuous_frame!`](finish_continuous_frame), as such
+/// images have no frame to be attached to.
+///
+/// # Panics
+///
+/// If the size of `image` does not match the dimensions, or the dimensions are not divisible by 4.
+pub fn frame_image(image: &[u8], width: u16, height: u16, offset: u8, origin: ImageOrigin) {
+    assert!(
+        (width | height) & 3 == 0,
+        "frame image dimensions must be divisible by 4",
+    );
+    assert_eq!(
+        image.len(),
+        usize::from(width) * usize::from(height) * 4,
+        "frame image size does not match its dimensions",
+    );
+    #[cfg(debug_assertions)]
+    {
+        static WARNED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+        if warn_about_frame_image(FRAME_MARKED.load(Ordering::Relaxed), &WARNED) {
+            color_message(
+                "Frame image emitted before the end of any frame was marked with \
+                 `finish_continuous_frame!()`, it has no frame to be attached to",
+                [0xFF, 0, 0],
+                0,
+            );
+        }
+    }
+    // SAFE: the image is as large as Tracy will read, as checked above.
+    #[cfg(feature="enable")]
+    unsafe {
+        sys::___tracy_emit_frame_image(
+            image.as_ptr() as _,
+            width,
+            height,
+            offset,
+            origin.flip().into(),
+        );
+    }
+}
+
+/// Whether a frame image emitted when `frame_marked` says if any frame was marked yet has no frame
+/// to be attached to and should be warned about.
+///
+/// `warned` is set by the first warning, so that only one is emitted.
+#[cfg(debug_assertions)]
+fn warn_about_frame_image(frame_marked: bool, warned: &std::sync::atomic::AtomicBool) -> bool {
+    !frame_marked && !warned.swap(true, Ordering::Relaxed)
+}
+
+/// Attach a screenshot to a frame, flipped vertically if `flip` is set.
+///
+/// This is [`frame_image`](frame_image) with the flag taken by the Tracy API: `true` stands for
+/// [`ImageOrigin::BottomLeft`](ImageOrigin::BottomLeft), as in OpenGL, and `false` for
+/// [`ImageOrigin::TopLeft`](ImageOrigin::TopLeft).
+///
+/// # Panics
+///
+/// If the size of `image` does not match the dimensions, or the dimensions are not divisible by 4.
+pub fn frame_image_flip(image: &[u8], width: u16, height: u16, offset: u8, flip: bool) {
+    frame_image(image, width, height, offset, ImageOrigin::from_flip(flip))
+}
+
+/// Limits how often [frame images](frame_image) are captured.
+///
+/// Frame images are expensive to capture, transfer and store, so they should be small and only
+/// captured every now and then. This decides whether the current frame should be captured.
+///
+/// # Examples
+///
+/// ```
+/// # use tracy_client::*;
+/// let mut throttle = FrameImageThrottle::new(2);
+/// # let image = [0; 4 * 4 * 4];
+/// // In the render loop:
+/// if throttle.should_capture() {
+///     frame_image(&image, 4, 4, 0, ImageOrigin::TopLeft);
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct FrameImageThrottle {
+    interval: Option<Duration>,
+    last: Option<Instant>,
+}
+
+impl FrameImageThrottle {
+    /// Allow capturing at most `images_per_second` frames each second.
+    ///
+    /// With 0 images per second no frames are captured.
+    pub fn new(images_per_second: u32) -> Self {
+        Self {
+            interval: Duration::from_secs(1).checked_div(images_per_second),
+            last: None,
+        }
+    }
+
+    /// Whether the current frame should be captured.
+    ///
+    /// The first call returns `true`, as do the calls at least `1 / images_per_second` seconds
+    /// after the last one that did.
+    pub fn should_capture(&mut self) -> bool {
+        let interval = match self.interval {
+            Some(interval) => interval,
+            None => return false,
+        };
+        let now = Instant::now();
+        match self.last {
+            Some(last) if now.duration_since(last) < interval => false,
+            _ => {
+                self.last = Some(now);
+                true
+            }
+        }
+    }
+}
+
+/// Output a message.
+///
+/// `callstack_depth` specifies the maximum number of stack frames client should collect.
+pub fn message(message: &str, callstack_depth: impl Into<StackDepth>) {
+    #[cfg(feature="enable")]
+    unsafe {
+        sys::___tracy_init_thread();
+        sys::___tracy_emit_message(
+            message.as_ptr() as _,
+            message.len(),
+            callstack_depth.into().frames().into(),
+        )
+    }
+}
+
+/// Output a message without a callstack.
+///
+/// Same as [`message`](message) with a callstack depth of 0, but states the intent in the type.
+pub fn message_no_callstack(message: &str) {
+    self::message(message, StackDepth::None)
+}
+
+/// Output a message with a callstack of at most `callstack_depth` stack frames.
+///
+/// Unlike with [`message`](message), the depth cannot be 0, so a callstack is always collected
+/// where Tracy supports it (see [`callstack_supported`](callstack_supported)).
+pub fn message_with_callstack(message: &str, callstack_depth: std::num::NonZeroU16) {
+    self::message(message, StackDepth::Frames(callstack_depth.get()))
+}
+
+/// Output each of the `lines` as a separate message.
+///
+/// This is equivalent to calling [`message`](message) for every line, but does the per-call
+/// setup only once, which makes it cheaper for bursts of messages such as diagnostic dumps.
+/// Lines longer than [`MAX_MESSAGE_LEN`] are truncated, as the lines of a dump are rarely checked
+/// beforehand.
+///
+/// `callstack_depth` specifies the maximum number of stack frames client should collect.
+pub fn messages<I>(lines: I, callstack_depth: impl Into<StackDepth>)
+where
+    I: IntoIterator,
+    I::Item: AsRef<str>,
+{
+    #[cfg(feature="enable")]
+    unsafe {
+        sys::___tracy_init_thread();
+        let depth = callstack_depth.into().frames().into();
+        for_each_message(lines, |line| {
+            sys::___tracy_emit_message(line.as_ptr() as _, line.len(), depth)
+        });
+    }
+}
+
+/// Pass each of the `lines` given to [`messages`](messages) to `emit`, truncated to
+/// `MAX_MESSAGE_LEN`.
+#[cfg_attr(not(feature="enable"), allow(dead_code))]
+fn for_each_message<I>(lines: I, mut emit: impl FnMut(&str))
+where
+    I: IntoIterator,
+    I::Item: AsRef<str>,
+{
+    for line in lines {
+        emit(truncate(line.as_ref()));
+    }
+}
+
+/// An adapter emitting the text written to it as Tracy messages, one message per line.
+///
+/// This allows redirecting textual output, such as logs or the output of a child process, to
+/// Tracy. Lines are only emitted once their terminating `\n` is written, so a line may be written
+/// in several parts. A trailing `\r` is removed, and invalid UTF-8 is replaced with `�`. Lines
+/// longer than [`MAX_MESSAGE_LEN`] are truncated. The last line is emitted even without a
+/// terminating `\n` when the writer is flushed or dropped.
+///
+/// # Examples
+///
+/// ```
+/// # use tracy_client::*;
+/// use std::io::Write;
+/// let mut writer = TracyWriter::new(0);
+/// writeln!(writer, "loaded {} assets", 42).unwrap();
+/// ```
+pub struct TracyWriter {
+    pending: Vec<u8>,
+    callstack_depth: StackDepth,
+}
+
+impl TracyWriter {
+    /// Create a writer whose messages collect at most `callstack_depth` stack frames.
+    pub fn new(callstack_depth: impl Into<StackDepth>) -> Self {
+        Self { pending: Vec::new(), callstack_depth: callstack_depth.into() }
+    }
+
+    fn emit(&self, line: &[u8]) {
+        let line = if line.ends_with(b"\r") { &line[..line.len() - 1] } else { line };
+        message(truncate(&String::from_utf8_lossy(line)), self.callstack_depth);
+    }
+}
+
+impl std::io::Write for TracyWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut rest = buf;
+        while let Some(end) = rest.iter().position(|&b| b == b'\n') {
+            if self.pending.is_empty() {
+                self.emit(&rest[..end]);
+            } else {
+                self.pending.extend_from_slice(&rest[..end]);
+                self.emit(&self.pending);
+                self.pending.clear();
+            }
+            rest = &rest[end + 1..];
+        }
+        self.pending.extend_from_slice(rest);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if !self.pending.is_empty() {
+            self.emit(&self.pending);
+            self.pending.clear();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for TracyWriter {
+    fn drop(&mut self) {
+        let _ = std::io::Write::flush(self);
+    }
+}
+
+/// Output a message with an associated color.
+///
+/// `callstack_depth` specifies the maximum number of stack frames client should collect.
+///
+/// See [`Color`](Color) for the ways to specify the colour.
+pub fn color_message(
+    message: &str,
+    color: impl Into<Color>,
+    callstack_depth: impl Into<StackDepth>,
+) {
+    #[cfg(feature="enable")]
+    unsafe {
+        sys::___tracy_init_thread();
+        sys::___tracy_emit_messageC(
+            message.as_ptr() as _,
+            message.len(),
+            color.into().0 >> 8,
+            callstack_depth.into().frames().into(),
+        )
+    }
+}
+
+/// A colour of a span or a message.
+///
+/// The colour is stored as RGBA, where the least significant 8 bits represent the alpha component
+/// and most significant 8 bits represent the red component. Tracy ignores the alpha component.
+///
+/// Besides a packed `u32`, a colour can be converted from an `[r, g, b]` or `[r, g, b, a]` array,
+/// or, with the `rgb` feature enabled, from the `RGB8` and `RGBA8` types of the `rgb` crate.
+/// Common colours are available as constants in the [`colors`](colors) module.
+///
+/// ```
+/// # use tracy_client::*;
+/// assert_eq!(Color::from([0xFF, 0x80, 0x00]), Color(0xFF8000FF));
+/// color_message("orange", [0xFF, 0x80, 0x00], 0);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Color(pub u32);
+
+impl From<u32> for Color {
+    fn from(rgba: u32) -> Self {
+        Color(rgba)
+    }
+}
+
+impl From<[u8; 3]> for Color {
+    fn from([r, g, b]: [u8; 3]) -> Self {
+        Color::from([r, g, b, 0xFF])
+    }
+}
+
+impl From<[u8; 4]> for Color {
+    fn from(rgba: [u8; 4]) -> Self {
+        Color(u32::from_be_bytes(rgba))
+    }
+}
+
+#[cfg(feature = "rgb")]
+impl From<rgb::RGB8> for Color {
+    fn from(color: rgb::RGB8) -> Self {
+        Color::from([color.r, color.g, color.b])
+    }
+}
+
+#[cfg(feature = "rgb")]
+impl From<rgb::RGBA8> for Color {
+    fn from(color: rgb::RGBA8) -> Self {
+        Color::from([color.r, color.g, color.b, color.a])
+    }
+}
+
+/// Named [`Color`](Color) constants.
+///
+/// The constants are packed as RGBA with an opaque alpha, as expected by
+/// [`Span::emit_color`](Span::emit_color) and [`color_message`](color_message) alike.
+///
+/// ```
+/// use tracy_client::{color_message, colors};
+/// assert_eq!(colors::ORANGE, [0xFF, 0x80, 0x00].into());
+/// color_message("disk almost full", colors::ORANGE, 0);
+/// ```
+pub mod colors {
+    use super::Color;
+
+    pub const BLACK: Color = Color(0x000000FF);
+    pub const WHITE: Color = Color(0xFFFFFFFF);
+    pub const GRAY: Color = Color(0x808080FF);
+    pub const RED: Color = Color(0xFF0000FF);
+    pub const GREEN: Color = Color(0x00FF00FF);
+    pub const BLUE: Color = Color(0x0000FFFF);
+    pub const YELLOW: Color = Color(0xFFFF00FF);
+    pub const CYAN: Color = Color(0x00FFFFFF);
+    pub const MAGENTA: Color = Color(0xFF00FFFF);
+    pub const ORANGE: Color = Color(0xFF8000FF);
+    pub const PURPLE: Color = Color(0x800080FF);
+}
+
+/// Add information about the profiled application to the trace.
+///
+/// Tracy has no notion of a program name other than the executable's name, so this is the way to
+/// describe the process, e.g. to tell apart captures of several instances of the same program.
+/// The information is shown in the trace information window of the viewer. This can be called at
+/// any time; with the `ondemand` feature the information is retained and sent to every viewer
+/// that connects later.
+pub fn app_info(info: &str) {
+    #[cfg(feature="enable")]
+    unsafe {
+        sys::___tracy_init_thread();
+        sys::___tracy_emit_message_appinfo(info.as_ptr() as _, info.len());
+    }
+}
+
+/// Set the current thread name to the provided value.
+pub fn set_thread_name(name: &str) {
+    #[cfg(feature="enable")]
+    unsafe {
+        let name = CString::new(name).unwrap();
+        // SAFE: `name` is a valid null-terminated string.
+        sys::___tracy_set_thread_name(name.as_ptr() as _)
+    }
+}
+
+/// Call `callback` every `interval` on a dedicated thread.
+///
+/// This is useful for periodically emitting measurements that are not tied to any particular
+/// code, such as system statistics, to [plots](Plot). The thread is named `tracy sampler`, both
+/// in Tracy and for the operating system. The callback is first called right after the thread
+/// starts.
+///
+/// The sampling stops, and the thread is joined, when the returned [`Sampler`](Sampler) is
+/// dropped. A panic in `callback` stops the sampling as well.
+///
+/// # Examples
+///
+/// ```
+/// # use tracy_client::*;
+/// static THREADS: Plot = create_plot!("threads");
+/// let _sampler = spawn_sampler(std::time::Duration::from_secs(1), || THREADS.point(4.0));
+/// ```
+pub fn spawn_sampler<F>(interval: Duration, mut callback: F) -> Sampler
+where
+    F: FnMut() + Send + 'static,
+{
+    const NAME: &str = "tracy sampler";
+    let (stop, stopped) = mpsc::channel::<()>();
+    let thread = thread::Builder::new()
+        .name(NAME.into())
+        .spawn(move || {
+            set_thread_name(NAME);
+            loop {
+                callback();
+                match stopped.recv_timeout(interval) {
+                    Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                    _ => break,
+                }
+            }
+        })
+        .expect("could not spawn the sampler thread");
+    Sampler {
+        stop: Some(stop),
+        thread: Some(thread),
+    }
+}
+
+/// A handle to the thread started by [`spawn_sampler`](spawn_sampler).
+///
+/// Stops the sampling when dropped.
+pub struct Sampler {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Drop for Sampler {
+    fn drop(&mut self) {
+        // Disconnecting the channel wakes the sampler up.
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Create an instance of plot that can plot arbitrary `f64` values.
+///
+/// # Examples
+///
+/// ```
+/// # use tracy_client::*;
+/// static TEMPERATURE: Plot = create_plot!("temperature");
+/// TEMPERATURE.point(37.0);
+/// ```
+#[macro_export]
+macro_rules! create_plot {
+    ($name: literal) => {
+        unsafe { $crate::Plot::new_unchecked(concat!($name, "\0")) }
+    };
+}
+
+/// A plot for plotting arbitary `f64` values.
+///
+/// Create with the [`create_plot`](create_plot) macro.
+pub struct Plot {
+    name: &'static str,
+}
+
+impl Plot {
+    /// Use `create_plot!` instead.
+    #[doc(hidden)]
+    pub const unsafe fn new_unchecked(name: &'static str) -> Self {
+        Self { name }
+    }
+
+    /// Create a plot with a name only known at runtime.
+    ///
+    /// The name is [interned](intern_name), so this should only be used with a small, fixed set
+    /// of names.
+    ///
+    /// # Panics
+    ///
+    /// If the name contains a null byte. See [`try_new_leak`](Plot::try_new_leak).
+    pub fn new_leak(name: &str) -> Self {
+        Self::try_new_leak(name).unwrap()
+    }
+
+    /// Create a plot with a name only known at runtime.
+    ///
+    /// The name is [interned](intern_name), so this should only be used with a small, fixed set
+    /// of names.
+    ///
+    /// Tracy would cut the name off at the first null byte, so names containing one are rejected.
+    pub fn try_new_leak(name: &str) -> Result<Self, NameError> {
+        // SAFE: interned names are null-terminated and live for the rest of the program.
+        intern_name(name).map(|name| unsafe { Self::new_unchecked(name) })
+    }
+
+    /// Add a point with `y`-axis value of `value` to the plot.
+    #[inline]
+    pub fn point(&self, value: f64) {
+        #[cfg(debug_assertions)]
+        self.check_value_type(PlotValue::F64(value));
+        self.emit(value);
+    }
+
+    /// Add a point with `y`-axis value of `value` to the plot.
+    ///
+    /// Unlike [`point`](Plot::point), this accepts any of the [`PlotValue`](PlotValue) types.
+    /// A plot should be given values of a single type: in debug builds, a warning is emitted as a
+    /// Tracy message the first time a plot with this name gets a value of a different type than
+    /// its first one, even if the values are given through different `Plot`s.
+    /// [`point`](Plot::point) and [`state`](Plot::state) count as `F64` values.
+    pub fn value(&self, value: impl Into<PlotValue>) {
+        let value = value.into();
+        #[cfg(debug_assertions)]
+        self.check_value_type(value);
+        self.emit(value.as_f64())
+    }
+
+    #[inline]
+    fn emit(&self, value: f64) {
+        #[cfg(feature="enable")]
+        unsafe {
+            sys::___tracy_emit_plot(self.name.as_ptr() as _, value);
+        }
+    }
+
+    /// Warn if `value` is of a different type than the first value of a plot with this name.
+    ///
+    /// Tracy identifies plots by the address of their name, so that is what the types are tracked
+    /// by. Returns whether a warning was emitted, which happens at most once per plot.
+    #[cfg(debug_assertions)]
+    fn check_value_type(&self, value: PlotValue) -> bool {
+        static VALUE_TYPES: Lazy<Mutex<HashMap<usize, u8>>> = Lazy::new();
+        let value_type = value.type_id();
+        {
+            let value_types = VALUE_TYPES.get_or_init(Default::default);
+            let mut value_types = value_types.lock().unwrap_or_else(|e| e.into_inner());
+            let first = value_types.entry(self.name.as_ptr() as usize).or_insert(value_type);
+            if *first == value_type || *first == MIXED_VALUE_TYPES {
+                return false;
+            }
+            *first = MIXED_VALUE_TYPES;
+        }
+        let name = self.name.trim_end_matches('\0');
+        color_message(
+            &format!("Plot `{}` got a value of a different type than its first value", name),
+            [0xFF, 0, 0],
+            0,
+        );
+        true
+    }
+
+    /// Add a point for an on/off state to the plot, with a value of 1 for on and 0 for off.
+    ///
+    /// The bundled Tracy version cannot configure a plot to be drawn as steps, so the plot is
+    /// drawn with lines between the points like any other.
+    pub fn state(&self, on: bool) {
+        self.point(if on { 1.0 } else { 0.0 })
+    }
+}
+
+/// Get a `'static`, null-terminated copy of `name`, for use as the name of a plot or a frame set.
+///
+/// Tracy identifies plots and frame sets by the address of their name, so every distinct name is
+/// leaked only once for the whole program and the same copy is returned to all the crates asking
+/// for it through the same version of `tracy-client`. This should still only be used with a small,
+/// fixed set of names.
+///
+/// Tracy would cut the name off at the first null byte, so names containing one are rejected.
+///
+/// Each thread remembers the names it has interned, so interning the same name again, e.g. for
+/// every event, does not take a lock.
+pub fn intern_name(name: &str) -> Result<&'static str, NameError> {
+    thread_local! {
+        /// The names this thread has already looked up in `INTERNED_NAMES`.
+        static CACHED_NAMES: RefCell<HashMap<String, &'static str>> = RefCell::new(HashMap::new());
+    }
+    // The cache is gone while the thread is being torn down, in which case the global map is used.
+    if let Ok(Some(interned)) = CACHED_NAMES.try_with(|names| names.borrow().get(name).copied()) {
+        return Ok(interned);
+    }
+    if let Some(position) = name.bytes().position(|b| b == 0) {
+        return Err(NameError { position });
+    }
+    let interned = {
+        let names = INTERNED_NAMES.get_or_init(Default::default);
+        let mut names = names.lock().unwrap_or_else(|e| e.into_inner());
+        match names.get(name) {
+            Some(&interned) => interned,
+            None => {
+                let mut leaked = String::with_capacity(name.len() + 1);
+                leaked.push_str(name);
+                leaked.push('\0');
+                let interned: &'static str = Box::leak(leaked.into_boxed_str());
+                names.insert(name.to_owned(), interned);
+                interned
+            }
+        }
+    };
+    let _ = CACHED_NAMES.try_with(|names| names.borrow_mut().insert(name.to_owned(), interned));
+    Ok(interned)
+}
+
+/// The names interned by all threads.
+static INTERNED_NAMES: Lazy<Mutex<HashMap<String, &'static str>>> = Lazy::new();
+
+/// A value created on first use, for statics that cannot be initialized in a `const` context.
+struct Lazy<T> {
+    once: Once,
+    value: UnsafeCell<Option<T>>,
+}
+
+// SAFE: the value is only written once, by `call_once`, before any reference to it is handed out.
+unsafe impl<T: Send + Sync> Sync for Lazy<T> {}
+
+impl<T> Lazy<T> {
+    const fn new() -> Self {
+        Self { once: Once::new(), value: UnsafeCell::new(None) }
+    }
+
+    fn get_or_init(&self, init: impl FnOnce() -> T) -> &T {
+        // SAFE: `call_once` runs the closure at most once and blocks other callers until it has.
+        self.once.call_once(|| unsafe { *self.value.get() = Some(init()) });
+        // SAFE: the value has been written above and is never written again.
+        unsafe { (*self.value.get()).as_ref().unwrap() }
+    }
+}
+
+/// An error indicating that a name contains a null byte.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NameError {
+    position: usize,
+}
+
+impl NameError {
+    /// The position of the first null byte in the name.
+    pub fn nul_position(&self) -> usize {
+        self.position
+    }
+}
+
+impl std::fmt::Display for NameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "name contains a null byte at position {}", self.position)
+    }
+}
 
-impl Plot {
-    /// Use `create_plot!` instead.
-    #[doc(hidden)]
-    pub const unsafe fn new_unchecked(name: &'static str) -> Self {
-        Self(name)
+impl std::error::Error for NameError {}
+
+/// The value type of a plot that has been warned about getting values of different types.
+#[cfg(debug_assertions)]
+const MIXED_VALUE_TYPES: u8 = std::u8::MAX;
+
+/// A value that can be added to a [`Plot`](Plot).
+///
+/// Tracy plots store `f64` values, so `I64` values with a magnitude larger than 2<sup>53</sup>
+/// lose precision.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlotValue {
+    F64(f64),
+    F32(f32),
+    I64(i64),
+}
+
+impl PlotValue {
+    /// A number identifying the variant of this value, other than `MIXED_VALUE_TYPES`.
+    #[cfg(debug_assertions)]
+    fn type_id(self) -> u8 {
+        match self {
+            PlotValue::F64(_) => 1,
+            PlotValue::F32(_) => 2,
+            PlotValue::I64(_) => 3,
+        }
     }
 
-    /// Add a point with `y`-axis value of `value` to the plot.
-    pub fn point(&self, value: f64) {
-        #[cfg(feature="enable")]
-        unsafe {
-            sys::___tracy_emit_plot(self.0.as_ptr() as _, value);
+    #[inline]
+    fn as_f64(self) -> f64 {
+        match self {
+            PlotValue::F64(value) => value,
+            PlotValue::F32(value) => value.into(),
+            PlotValue::I64(value) => value as f64,
+        }
+    }
+}
+
+impl From<f64> for PlotValue {
+    fn from(value: f64) -> Self {
+        PlotValue::F64(value)
+    }
+}
+
+impl From<f32> for PlotValue {
+    fn from(value: f32) -> Self {
+        PlotValue::F32(value)
+    }
+}
+
+impl From<i64> for PlotValue {
+    fn from(value: i64) -> Self {
+        PlotValue::I64(value)
+    }
+}
+
+/// The maximum number of stack frames to collect.
+///
+/// Functions taking a callstack depth accept either a `StackDepth` or a plain `u16`, in which case
+/// 0 means that no callstack will be collected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StackDepth {
+    /// Do not collect a callstack.
+    None,
+    /// Collect at most the specified number of stack frames.
+    Frames(u16),
+    /// Use the depth set with [`set_default_stack_depth`](set_default_stack_depth).
+    Default,
+}
+
+static DEFAULT_STACK_DEPTH: AtomicU16 = AtomicU16::new(0);
+
+impl StackDepth {
+    /// The number of frames to collect, adjusted to the maximum supported by tracy.
+    #[inline]
+    #[cfg_attr(not(feature="enable"), allow(dead_code))]
+    fn frames(self) -> u16 {
+        match self {
+            StackDepth::None => 0,
+            StackDepth::Frames(depth) => adjust_stack_depth(depth),
+            StackDepth::Default => {
+                adjust_stack_depth(DEFAULT_STACK_DEPTH.load(Ordering::Relaxed))
+            }
+        }
+    }
+}
+
+impl From<u16> for StackDepth {
+    fn from(depth: u16) -> Self {
+        match depth {
+            0 => StackDepth::None,
+            depth => StackDepth::Frames(depth),
         }
     }
 }
 
+/// Set the callstack depth used wherever [`StackDepth::Default`](StackDepth::Default) is
+/// specified.
+///
+/// Defaults to 0, which means that no callstack will be collected.
+pub fn set_default_stack_depth(callstack_depth: u16) {
+    DEFAULT_STACK_DEPTH.store(callstack_depth, Ordering::Relaxed);
+}
+
+/// Panic unless the Tracy client library is linked into the program.
+///
+/// Without the `enable` feature, which is easy to lose when this crate is depended upon
+/// through another crate with `default-features = false`, all of this crate's functions silently
+/// do nothing. Calling this function early, e.g. in `main` of a build that is supposed to be
+/// profiled, turns such a misconfiguration into a loud failure. With the feature enabled, this
+/// calls into the client library, so the library is guaranteed to be linked as well.
+pub fn assert_linked() {
+    #[cfg(feature="enable")]
+    unsafe {
+        sys::___tracy_init_thread();
+    }
+    #[cfg(not(feature="enable"))]
+    panic!(
+        "tracy-client is built without the `enable` feature, so no profiling data is collected"
+    );
+}
+
+/// Whether Tracy can collect callstacks on the target this crate has been built for.
+///
+/// When this returns `false`, requesting a non-zero callstack depth only adds overhead, as the
+/// zones, messages and memory events will not have any callstacks attached. Tracy has no runtime
+/// query for this, so the answer is derived from the target the same way Tracy decides it.
+///
+/// A `true` result does not guarantee useful callstacks: a binary stripped of its debug
+/// information still has its callstacks collected, but they cannot be resolved to symbols.
+pub const fn callstack_supported() -> bool {
+    cfg!(all(
+        feature = "enable",
+        any(
+            windows,
+            target_os = "android",
+            target_os = "linux",
+            target_vendor = "apple",
+            target_os = "freebsd",
+            target_os = "dragonfly",
+            target_os = "netbsd",
+            target_os = "openbsd",
+        )
+    ))
+}
+
+/// Whether Tracy can sample the callstacks of the program on the target this crate has been
+/// built for.
+///
+/// Sampling is part of Tracy's system tracing, which also collects the context switches (see
+/// [`context_switch_supported`](context_switch_supported)). It requires elevated privileges: on
+/// Linux the program has to run as root, on Windows as an administrator. Without them nothing is
+/// collected, and Tracy has no way to query this at runtime, so this only tells whether the
+/// support has been compiled in.
+pub const fn sampling_supported() -> bool {
+    system_tracing_supported()
+}
+
+/// Whether Tracy can collect the context switches of the program's threads on the target this
+/// crate has been built for.
+///
+/// This has the same requirements as [`sampling_supported`](sampling_supported).
+pub const fn context_switch_supported() -> bool {
+    system_tracing_supported()
+}
+
+/// Whether the bundled Tracy implements system tracing for the target.
+const fn system_tracing_supported() -> bool {
+    cfg!(all(feature = "enable", any(windows, target_os = "linux", target_os = "android")))
+}
+
+/// List the Tracy features this crate has been built with.
+///
+/// The names match the cargo features of this crate. This is mostly useful for diagnostics, e.g.
+/// when reporting an issue.
+pub fn enabled_features() -> &'static [&'static str] {
+    &[
+        #[cfg(feature="enable")]
+        "enable",
+        #[cfg(feature="delayed-init")]
+        "delayed-init",
+        #[cfg(feature="lowres-timer")]
+        "lowres-timer",
+        #[cfg(feature="noexit")]
+        "noexit",
+        #[cfg(feature="ondemand")]
+        "ondemand",
+    ]
+}
+
 /// Adjust the stack depth to maximum supported by tracy.
+///
+/// On Windows Tracy collects at most 62 stack frames and asserts that no more are requested, so
+/// larger depths are clamped to 62. On the other targets the depth is returned unchanged.
+///
+/// All the APIs of this crate taking a callstack depth apply this adjustment already. It is
+/// useful for code passing depths to the `tracy-client-sys` functions directly.
+///
+/// The clamping is branchless: when `depth < 62` the mask is all ones and the result is
+/// `62 ^ depth ^ 62 = depth`, otherwise the mask is zero and the result is `62`.
 #[inline(always)]
+pub const fn adjust_stack_depth(depth: u16) -> u16 {
+    target_stack_depth(depth)
+}
+
 #[cfg(windows)]
-const fn adjust_stack_depth(depth: u16) -> u16 {
+#[inline(always)]
+const fn target_stack_depth(depth: u16) -> u16 {
     62 ^ ((depth ^ 62) & 0u16.wrapping_sub((depth < 62) as _))
 }
 
-/// Adjust the stack depth to maximum supported by tracy.
-#[inline(always)]
 #[cfg(not(windows))]
-const fn adjust_stack_depth(depth: u16) -> u16 {
+#[inline(always)]
+const fn target_stack_depth(depth: u16) -> u16 {
     depth
 }
 
@@ -357,6 +2172,445 @@ mod tests {
         let span = Span::new("test zone values", "zone_values", file!(), line!(), 100);
         span.emit_value(42);
         span.emit_text("some text");
+        span.emit_bytes(b"\x00\xFFsome bytes\xC3");
+    }
+
+    #[test]
+    fn formatted_text() {
+        struct Nested<'a>(&'a Span);
+        impl std::fmt::Display for Nested<'_> {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                self.0.emit_debug("inner");
+                f.write_str("outer")
+            }
+        }
+        let span = Span::new("formatted text", "formatted_text", file!(), line!(), 0);
+        span.emit_display(42);
+        span.emit_debug(Some("debug"));
+        span.emit_display(Nested(&span));
+        span.emit_display("é".repeat(MAX_MESSAGE_LEN));
+        SCRATCH.with(|scratch| assert_eq!(*scratch.borrow(), "é".repeat(MAX_MESSAGE_LEN)));
+    }
+
+    #[test]
+    fn annotated_zones() {
+        let span = Span::new_annotated(
+            "annotated", "annotated_zones", file!(), line!(), 0,
+            Some(Color(0xFF0000FF)), Some("some text"), Some(42),
+        );
+        span.emit_color(0x00FF00FF);
+        let _bare = Span::new_annotated(
+            "bare", "annotated_zones", file!(), line!(), 0, None, None, None,
+        );
+        let all: Vec<_> = Annotation::all(Some(Color(0xFF0000FF)), Some("some text"), Some(42))
+            .collect();
+        assert_eq!(all, [
+            Annotation::Color(Color(0xFF0000FF)),
+            Annotation::Text("some text"),
+            Annotation::Value(42),
+        ]);
+        let text: Vec<_> = Annotation::all(None, Some("only text"), None).collect();
+        assert_eq!(text, [Annotation::Text("only text")]);
+        let value: Vec<_> = Annotation::all(None, None, Some(0)).collect();
+        assert_eq!(value, [Annotation::Value(0)]);
+        assert_eq!(Annotation::all(None, None, None).count(), 0);
+    }
+
+    #[test]
+    fn colored_on_drop() {
+        let called = std::cell::Cell::new(false);
+        {
+            let span = Span::new("colored on drop", "colored_on_drop", file!(), line!(), 0)
+                .color_on_drop(|| {
+                    called.set(true);
+                    [0xFF, 0, 0]
+                });
+            span.emit_value(1);
+            assert!(!called.get());
+        }
+        assert!(called.get());
+    }
+
+    #[test]
+    fn messages_with_and_without_callstacks() {
+        message_no_callstack("no callstack");
+        for &depth in &[1, 62, 63, u16::max_value()] {
+            let depth = std::num::NonZeroU16::new(depth).unwrap();
+            assert_ne!(StackDepth::Frames(depth.get()).frames(), 0);
+            message_with_callstack("with callstack", depth);
+        }
+    }
+
+    #[test]
+    fn tracy_writer() {
+        use std::io::Write;
+        let mut writer = TracyWriter::new(0);
+        writer.write_all(b"first\nsec").unwrap();
+        assert_eq!(writer.pending, b"sec");
+        writer.write_all(b"ond\r\nthird\n\n").unwrap();
+        assert!(writer.pending.is_empty());
+        write!(writer, "partial {}", 1).unwrap();
+        writer.write_all(&[0xFF, b'\n']).unwrap();
+        writer.write_all(b"unterminated").unwrap();
+        assert_eq!(writer.pending, b"unterminated");
+        writer.flush().unwrap();
+        assert!(writer.pending.is_empty());
+        writer.write_all(&b"x".repeat(MAX_MESSAGE_LEN + 1)).unwrap();
+        drop(writer);
+    }
+
+    #[test]
+    fn message_batches() {
+        let snapshot = "state:\n  frames = 10\n  entities = 3";
+        messages(snapshot.lines(), 0);
+        messages(vec![String::from("owned"), String::from("lines")], StackDepth::Default);
+        messages(std::iter::empty::<&str>(), 10);
+        messages(&["é".repeat(MAX_MESSAGE_LEN)], 0);
+
+        let mut emitted = Vec::new();
+        let long = format!("{}{}", "l".repeat(MAX_MESSAGE_LEN), "cut off");
+        for_each_message(vec!["first", "", &long, "last"], |line| emitted.push(line.to_owned()));
+        assert_eq!(emitted, ["first", "", &"l".repeat(MAX_MESSAGE_LEN), "last"]);
+        // The limit falls in the middle of an `é`, which is left out entirely.
+        let mut emitted = Vec::new();
+        let misaligned = format!("x{}", "é".repeat(MAX_MESSAGE_LEN));
+        for_each_message(&[misaligned], |line| emitted.push(line.len()));
+        assert_eq!(emitted, [MAX_MESSAGE_LEN - 1]);
+    }
+
+    #[test]
+    fn colors() {
+        assert_eq!(Color::from(0x12345678), Color(0x12345678));
+        assert_eq!(Color::from([0x12, 0x34, 0x56]), Color(0x123456FF));
+        assert_eq!(Color::from([0x12, 0x34, 0x56, 0x78]), Color(0x12345678));
+        color_message("colored", [0xFF, 0, 0], 0);
+    }
+
+    #[test]
+    fn named_colors() {
+        assert_eq!(colors::RED.0, 0xFF0000FF);
+        assert_eq!(colors::BLUE.0, 0x0000FFFF);
+        assert_eq!(colors::WHITE, Color::from([0xFF, 0xFF, 0xFF]));
+        assert_eq!(colors::ORANGE, Color::from([0xFF, 0x80, 0x00, 0xFF]));
+        color_message("red", colors::RED, 0);
+        Span::new("green", "named_colors", file!(), line!(), 0).emit_color(colors::GREEN);
+    }
+
+    #[test]
+    #[cfg(feature = "rgb")]
+    fn rgb_colors() {
+        assert_eq!(Color::from(rgb::RGB8::new(0x12, 0x34, 0x56)), Color(0x123456FF));
+        assert_eq!(Color::from(rgb::RGBA8::new(0x12, 0x34, 0x56, 0x78)), Color(0x12345678));
+    }
+
+    #[test]
+    fn secure_and_insecure_allocators() {
+        use std::alloc::GlobalAlloc;
+        let secure = ProfiledAllocator::new(alloc::System, 0);
+        // SAFE: the profiler is running for the duration of the tests.
+        let insecure = unsafe { ProfiledAllocator::new_insecure(alloc::System, 10) };
+        let layout = alloc::Layout::from_size_align(64, 8).unwrap();
+        for allocator in &[secure, insecure] {
+            unsafe {
+                let ptr = allocator.alloc(layout);
+                assert!(!ptr.is_null());
+                allocator.dealloc(ptr, layout);
+            }
+        }
+    }
+
+    #[test]
+    fn allocator_builder() {
+        use std::alloc::GlobalAlloc;
+        const LARGE_ONLY: ProfiledAllocator<alloc::System> =
+            ProfiledAllocator::builder(alloc::System).callstack_depth(8).min_size(64).build();
+        // SAFE: the profiler is running for the duration of the tests.
+        const INSECURE: ProfiledAllocator<alloc::System> =
+            unsafe { ProfiledAllocator::builder(alloc::System).insecure().build() };
+        assert_eq!(LARGE_ONLY.min_size, 64);
+        assert_eq!((LARGE_ONLY.secure, INSECURE.secure), (true, false));
+        for &size in &[16, 64, 256] {
+            let layout = alloc::Layout::from_size_align(size, 8).unwrap();
+            for allocator in &[LARGE_ONLY, INSECURE] {
+                unsafe {
+                    let ptr = allocator.alloc(layout);
+                    assert!(!ptr.is_null());
+                    let ptr = allocator.realloc(ptr, layout, size * 2);
+                    let layout = alloc::Layout::from_size_align(size * 2, 8).unwrap();
+                    allocator.dealloc(ptr, layout);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reallocation() {
+        use std::alloc::GlobalAlloc;
+        /// Grows in place within a fixed capacity and fails beyond it.
+        struct InPlace;
+        unsafe impl GlobalAlloc for InPlace {
+            unsafe fn alloc(&self, layout: alloc::Layout) -> *mut u8 {
+                let capacity = alloc::Layout::from_size_align(128, layout.align()).unwrap();
+                alloc::System.alloc(capacity)
+            }
+            unsafe fn dealloc(&self, ptr: *mut u8, layout: alloc::Layout) {
+                let capacity = alloc::Layout::from_size_align(128, layout.align()).unwrap();
+                alloc::System.dealloc(ptr, capacity)
+            }
+            unsafe fn realloc(&self, ptr: *mut u8, _: alloc::Layout, size: usize) -> *mut u8 {
+                if size <= 128 { ptr } else { std::ptr::null_mut() }
+            }
+        }
+        let allocator = ProfiledAllocator::new(InPlace, 0);
+        let layout = alloc::Layout::from_size_align(16, 8).unwrap();
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            assert_eq!(allocator.realloc(ptr, layout, 64), ptr);
+            assert!(reallocation_changed(ptr, 16, ptr, 64));
+            let layout = alloc::Layout::from_size_align(64, 8).unwrap();
+            assert_eq!(allocator.realloc(ptr, layout, 64), ptr);
+            assert!(!reallocation_changed(ptr, 64, ptr, 64));
+            assert!(allocator.realloc(ptr, layout, 256).is_null());
+            assert!(!reallocation_changed(ptr, 64, std::ptr::null_mut(), 256));
+            assert!(reallocation_changed(ptr, 64, ptr.add(64), 64));
+            allocator.dealloc(ptr, layout);
+        }
+    }
+
+    #[test]
+    fn discontinuous_zones() {
+        let mut pending = HashMap::new();
+        for request in 0..3 {
+            let token = begin_discontinuous("request", "discontinuous_zones", file!(), line!(), 0);
+            pending.insert(request, token);
+        }
+        for request in (0..3).rev() {
+            assert!(end_discontinuous(pending[&request]));
+        }
+        assert!(!end_discontinuous(pending[&0]));
+        let token = begin_discontinuous("other thread", "discontinuous_zones", file!(), line!(), 0);
+        assert!(!std::thread::spawn(move || end_discontinuous(token)).join().unwrap());
+        let ended_other = std::thread::spawn(move || {
+            let own = begin_discontinuous("own zone", "discontinuous_zones", file!(), line!(), 0);
+            (end_discontinuous(token), end_discontinuous(own))
+        });
+        assert_eq!(ended_other.join().unwrap(), (false, true));
+        assert!(end_discontinuous(token));
+    }
+
+    #[test]
+    #[cfg(all(feature="enable", debug_assertions))]
+    fn span_dropped_on_another_thread() {
+        struct ForceSend(Span);
+        // SAFE: not safe at all, which is what this test checks for.
+        unsafe impl Send for ForceSend {}
+        let span = Span::new("moved", "span_dropped_on_another_thread", file!(), line!(), 0);
+        let span = ForceSend(span);
+        assert!(std::thread::spawn(move || drop(span.0)).join().is_err());
+    }
+
+    #[test]
+    fn cstr_spans() {
+        let name = CString::new("cstr span").unwrap();
+        for depth in 0..2 {
+            let span = span_cstr!(&name, depth);
+            span.emit_value(depth.into());
+        }
+    }
+
+    #[test]
+    fn source_location_pool() {
+        static POOL: SourceLocationPool = SourceLocationPool::new();
+        assert!(POOL.is_empty());
+        let first = POOL.get("pooled", "source_location_pool", file!(), 1);
+        for i in 0..3 {
+            let location = POOL.get("pooled", "source_location_pool", file!(), 1);
+            assert!(std::ptr::eq(location, first));
+            Span::from_location(location, i).emit_value(i.into());
+        }
+        let other = POOL.get("pooled", "source_location_pool", file!(), 2);
+        #[cfg(feature="enable")]
+        assert!(!std::ptr::eq(other, first));
+        Span::from_location(other, 0);
+        assert_eq!(POOL.len(), 2);
+        let pool = SourceLocationPool::default();
+        let location = pool.get("pooled", "source_location_pool", file!(), 1);
+        #[cfg(feature="enable")]
+        assert!(!std::ptr::eq(location, first));
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn leaked_location() {
+        let location = SourceLocation::new_leak("leaked", "leaked_location", file!(), line!());
+        for i in 0..3 {
+            Span::from_location(location, i).emit_value(i.into());
+        }
+        let nameless = SourceLocation::new_leak("", "leaked_location", file!(), line!());
+        Span::from_location(nameless, 0);
+    }
+
+    #[test]
+    fn accumulating_spans() {
+        let location = SourceLocation::new_leak("accumulating", "accumulating_spans", file!(), 1);
+        let mut span = AccumulatingSpan::new(location, 0);
+        drop(span.enter());
+        for poll in 0..3 {
+            span.append(&format!("poll {}", poll));
+            let _zone = span.enter();
+        }
+        assert_eq!(span.text(), "poll 0\npoll 1\npoll 2");
+        span.append(&"a".repeat(MAX_MESSAGE_LEN));
+        drop(span.enter());
+        let span = std::thread::spawn(move || span).join().unwrap();
+        drop(span.enter());
+    }
+
+    #[test]
+    fn instant_zones() {
+        static LOCATION: SourceLocation = unsafe {
+            SourceLocation::new_unchecked("instant_zones\0", concat!(file!(), "\0"), line!())
+        };
+        for depth in 0..3 {
+            Span::instant(&LOCATION, depth);
+        }
+        Span::instant(SourceLocation::new_leak("leaked", "instant_zones", file!(), line!()), 0);
+    }
+
+    #[test]
+    fn scopes() {
+        let result = Span::scope("scope", "scopes", file!(), line!(), 0, || 42);
+        assert_eq!(result, 42);
+        let value = String::from("moved");
+        assert_eq!(scope!("scope macro", move || value), "moved");
+        assert_eq!(scope!("scope macro with depth", 10, || scope!("nested", || 1) + 1), 2);
+    }
+
+    #[test]
+    #[allow(clippy::assertions_on_constants)]
+    fn limits() {
+        assert!(MAX_ZONE_NAME_LEN <= MAX_MESSAGE_LEN);
+        assert_eq!(MAX_ZONE_NAME_LEN + SOURCE_LOCATION_OVERHEAD, usize::from(std::u16::MAX));
+        message(&"m".repeat(MAX_MESSAGE_LEN), 0);
+        let name = "n".repeat(MAX_ZONE_NAME_LEN - "limits".len() - file!().len());
+        let span = Span::new(&name, "limits", file!(), line!(), 0);
+        span.emit_text(&"t".repeat(MAX_MESSAGE_LEN));
+    }
+
+    #[test]
+    fn function_spans() {
+        fn instrumented() -> u32 {
+            span_fn!();
+            42
+        }
+        assert_eq!(instrumented(), 42);
+        let closure = || {
+            span_fn!(10);
+        };
+        closure();
+        assert_eq!(function_name("a::b::f"), "a::b");
+        assert_eq!(function_name("a::b::{{closure}}::{{closure}}::f"), "a::b");
+    }
+
+    #[test]
+    fn nameless_span() {
+        let span = Span::new("", "nameless_span", file!(), line!(), 0);
+        span.emit_value(42);
+    }
+
+    #[test]
+    fn callstack_support() {
+        let supported: bool = callstack_supported();
+        if cfg!(all(feature = "enable", target_os = "linux")) {
+            assert!(supported);
+        }
+        if cfg!(not(feature = "enable")) {
+            assert!(!supported);
+        }
+    }
+
+    #[test]
+    fn system_tracing_support() {
+        let sampling: bool = sampling_supported();
+        let context_switches: bool = context_switch_supported();
+        assert_eq!(sampling, context_switches);
+        let targets = cfg!(any(windows, target_os = "linux", target_os = "android"));
+        assert_eq!(sampling, cfg!(feature = "enable") && targets);
+    }
+
+    #[test]
+    fn adjusted_stack_depths() {
+        for &depth in &[0, 1, 61, 62, 63, 100, u16::max_value()] {
+            let expected = if cfg!(windows) { depth.min(62) } else { depth };
+            assert_eq!(adjust_stack_depth(depth), expected);
+        }
+    }
+
+    #[test]
+    fn stack_depths() {
+        assert_eq!(StackDepth::from(0), StackDepth::None);
+        assert_eq!(StackDepth::from(10), StackDepth::Frames(10));
+        assert_eq!(StackDepth::None.frames(), 0);
+        assert_eq!(StackDepth::Frames(10).frames(), 10);
+        set_default_stack_depth(20);
+        assert_eq!(StackDepth::Default.frames(), 20);
+
+        for depth in &[StackDepth::None, StackDepth::Frames(10), StackDepth::Default] {
+            let _span = Span::new("stack depths", "stack_depths", file!(), line!(), *depth);
+            message("stack depths", *depth);
+            color_message("stack depths", 0xFF00FF00, *depth);
+        }
+    }
+
+    #[test]
+    fn instrumented_future() {
+        use std::task::{RawWaker, RawWakerVTable, Waker};
+
+        struct Countdown(u32);
+        impl Future for Countdown {
+            type Output = u32;
+            fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<u32> {
+                if self.0 == 0 {
+                    return Poll::Ready(42);
+                }
+                self.0 -= 1;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+
+        fn noop_raw_waker() -> RawWaker {
+            fn clone(_: *const ()) -> RawWaker {
+                noop_raw_waker()
+            }
+            fn noop(_: *const ()) {}
+            static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+            RawWaker::new(std::ptr::null(), &VTABLE)
+        }
+
+        let waker = unsafe { Waker::from_raw(noop_raw_waker()) };
+        let mut cx = Context::from_waker(&waker);
+        let mut future = Box::pin(instrument_future(
+            "countdown",
+            "instrumented_future",
+            file!(),
+            line!(),
+            0,
+            Countdown(3),
+        ));
+        let result = loop {
+            if let Poll::Ready(result) = future.as_mut().poll(&mut cx) {
+                break result;
+            }
+        };
+        assert_eq!(result, 42);
+        assert_eq!(future.polls, 4);
+    }
+
+    #[test]
+    fn application_info() {
+        app_info("tracy-client tests");
+        app_info(&format!("process id {}", std::process::id()));
     }
 
     #[test]
@@ -373,11 +2627,108 @@ mod tests {
         }
     }
 
+    #[test]
+    fn frame_images() {
+        assert!(!ImageOrigin::TopLeft.flip());
+        assert!(ImageOrigin::BottomLeft.flip());
+        for &origin in &[ImageOrigin::TopLeft, ImageOrigin::BottomLeft] {
+            assert_eq!(ImageOrigin::from_flip(origin.flip()), origin);
+        }
+        let image = [0x80; 8 * 4 * 4];
+        frame_image(&image, 8, 4, 0, ImageOrigin::TopLeft);
+        frame_image(&image, 4, 8, 1, ImageOrigin::BottomLeft);
+        frame_image_flip(&image, 4, 8, 1, true);
+        finish_continuous_frame!("not the main frame set");
+        finish_continuous_frame!();
+        #[cfg(debug_assertions)]
+        assert!(FRAME_MARKED.load(Ordering::Relaxed));
+        frame_image(&image, 8, 4, 0, ImageOrigin::TopLeft);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn frame_image_warnings() {
+        use std::sync::atomic::AtomicBool;
+        let warned = AtomicBool::new(false);
+        assert!(!warn_about_frame_image(true, &warned));
+        assert!(warn_about_frame_image(false, &warned));
+        assert!(!warn_about_frame_image(false, &warned));
+        assert!(!warn_about_frame_image(true, &warned));
+        let warned = AtomicBool::new(false);
+        assert!(warn_about_frame_image(false, &warned));
+        assert!(warned.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn frame_iterations() {
+        static LOCATION: SourceLocation = unsafe {
+            SourceLocation::new_unchecked("frame_iterations\0", concat!(file!(), "\0"), line!())
+        };
+        for frame in 0..3u64 {
+            let iteration = FrameIteration::new(&LOCATION, 0);
+            iteration.emit_value(frame);
+            drop(iteration);
+            #[cfg(debug_assertions)]
+            assert!(FRAME_MARKED.load(Ordering::Relaxed));
+        }
+    }
+
+    #[test]
+    fn frame_image_throttle() {
+        let mut throttle = FrameImageThrottle::new(1);
+        assert!(throttle.should_capture());
+        assert!(!throttle.should_capture());
+        assert!(!throttle.should_capture());
+        let mut fast = FrameImageThrottle::new(1000);
+        assert!(fast.should_capture());
+        std::thread::sleep(Duration::from_millis(2));
+        assert!(fast.should_capture());
+        assert!(!FrameImageThrottle::new(0).should_capture());
+    }
+
+    #[test]
+    #[should_panic]
+    fn frame_image_size_mismatch() {
+        frame_image(&[0; 4 * 4 * 4], 8, 4, 0, ImageOrigin::TopLeft);
+    }
+
     #[test]
     fn non_continuous_frameset() {
         let _: Frame = start_noncontinuous_frame!("weird frameset");
     }
 
+    #[test]
+    #[cfg(feature="enable")]
+    fn features_include_enable() {
+        assert!(enabled_features().contains(&"enable"));
+    }
+
+    #[test]
+    #[cfg_attr(not(feature="enable"), should_panic)]
+    fn linked() {
+        assert_linked();
+    }
+
+    #[test]
+    fn sampler() {
+        use std::sync::atomic::AtomicUsize;
+        use std::sync::Arc;
+        static SAMPLES: Plot = create_plot!("sampler test");
+        let calls = Arc::new(AtomicUsize::new(0));
+        let sampler_calls = Arc::clone(&calls);
+        let sampler = spawn_sampler(Duration::from_millis(1), move || {
+            SAMPLES.point(1.0);
+            sampler_calls.fetch_add(1, Ordering::Relaxed);
+        });
+        while calls.load(Ordering::Relaxed) < 2 {
+            std::thread::yield_now();
+        }
+        drop(sampler);
+        let after_stop = calls.load(Ordering::Relaxed);
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(calls.load(Ordering::Relaxed), after_stop);
+    }
+
     #[test]
     fn plot_something() {
         static PLOT: Plot = create_plot!("a plot");
@@ -385,4 +2736,83 @@ mod tests {
             PLOT.point(i as f64);
         }
     }
+
+    #[test]
+    fn leaked_plot_names() {
+        Plot::new_leak("leaked plot").point(1.0);
+        Plot::try_new_leak(&format!("plot {}", 2)).unwrap().point(2.0);
+        let error = Plot::try_new_leak("bad\0plot").err().unwrap();
+        assert_eq!(error.nul_position(), 3);
+        assert_eq!(error.to_string(), "name contains a null byte at position 3");
+    }
+
+    #[test]
+    fn interned_names() {
+        mod first {
+            pub fn name() -> &'static str {
+                super::intern_name("shared name").unwrap()
+            }
+        }
+        mod second {
+            pub fn name() -> &'static str {
+                super::intern_name(&String::from("shared name")).unwrap()
+            }
+        }
+        assert_eq!(first::name(), "shared name\0");
+        assert_eq!(first::name().as_ptr(), second::name().as_ptr());
+        assert_ne!(intern_name("other name").unwrap().as_ptr(), first::name().as_ptr());
+        assert_eq!(intern_name("a\0b").unwrap_err().nul_position(), 1);
+        assert_eq!(Plot::new_leak("shared name").name.as_ptr(), first::name().as_ptr());
+        let elsewhere = std::thread::spawn(|| first::name().as_ptr() as usize).join().unwrap();
+        assert_eq!(elsewhere, first::name().as_ptr() as usize);
+    }
+
+    #[test]
+    fn interned_names_are_cached_per_thread() {
+        let name = intern_name("cached name").unwrap();
+        // Names this thread has seen already are found without locking the global map.
+        let _names = INTERNED_NAMES.get_or_init(Default::default).lock().unwrap();
+        assert_eq!(intern_name("cached name").unwrap().as_ptr(), name.as_ptr());
+    }
+
+    #[test]
+    #[should_panic]
+    fn leaked_plot_name_with_nul() {
+        Plot::new_leak("\0");
+    }
+
+    #[test]
+    fn plot_values() {
+        static PLOT: Plot = create_plot!("plot values");
+        assert_eq!(PlotValue::from(1.5f64).as_f64(), 1.5);
+        assert_eq!(PlotValue::from(2.5f32).as_f64(), 2.5);
+        assert_eq!(PlotValue::from(-3i64).as_f64(), -3.0);
+        PLOT.value(1.5f64);
+        PLOT.value(2.5f32);
+        PLOT.value(-3i64);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn plot_value_type_mismatches() {
+        static PLOT: Plot = create_plot!("mixed plot");
+        assert!(!PLOT.check_value_type(PlotValue::F64(1.0)));
+        assert!(!PLOT.check_value_type(PlotValue::F64(2.0)));
+        assert!(PLOT.check_value_type(PlotValue::I64(3)));
+        assert!(!PLOT.check_value_type(PlotValue::F32(4.0)));
+        let other = Plot::new_leak("another mixed plot");
+        assert!(!other.check_value_type(PlotValue::I64(1)));
+        assert!(!other.check_value_type(PlotValue::I64(2)));
+        let again = Plot::new_leak("another mixed plot");
+        assert!(again.check_value_type(PlotValue::F32(3.0)));
+        assert!(!other.check_value_type(PlotValue::F64(4.0)));
+    }
+
+    #[test]
+    fn plot_states() {
+        static VSYNC: Plot = create_plot!("vsync enabled");
+        for frame in 0..10 {
+            VSYNC.state(frame & 4 == 0);
+        }
+    }
 }